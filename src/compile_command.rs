@@ -0,0 +1,621 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use regex::Regex;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::parse::DEFAULT_SOURCE_EXTENSIONS;
+use crate::shell;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CompileCommand {
+    pub directory: String,
+    pub command: String,
+    pub file: String,
+    // --emit-compiler 时记录编译器名 (不含路径), 默认不输出, 保持与规范一致
+    #[serde(default)]
+    pub compiler: Option<String>,
+    // --read-dep-files 时记录依赖文件中列出的头文件, 同样是规范之外的扩展字段
+    #[serde(default)]
+    pub headers: Option<Vec<String>>,
+}
+
+// 字段按固定的顺序输出: directory、file、command, 之后是有值的扩展字段 compiler、headers
+// 顺序与结构体的声明无关, 以后增加字段也不会打乱已有字段, 不同版本生成的数据库可以直接 diff
+impl Serialize for CompileCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 3 + usize::from(self.compiler.is_some()) + usize::from(self.headers.is_some());
+        let mut entry = serializer.serialize_struct("CompileCommand", len)?;
+        entry.serialize_field("directory", &self.directory)?;
+        entry.serialize_field("file", &self.file)?;
+        entry.serialize_field("command", &self.command)?;
+        if let Some(compiler) = &self.compiler {
+            entry.serialize_field("compiler", compiler)?;
+        }
+        if let Some(headers) = &self.headers {
+            entry.serialize_field("headers", headers)?;
+        }
+        entry.end()
+    }
+}
+
+// 不符合 compile_commands.json 规范的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    // 必需的字段为空
+    EmptyField(&'static str),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyField(field) => write!(f, "`{}` is empty", field),
+        }
+    }
+}
+
+// 按 compile_commands.json 规范检查一个条目: directory、file 非空, 且有 command
+pub fn validate_entry(cc: &CompileCommand) -> Vec<ValidationError> {
+    [
+        ("directory", &cc.directory),
+        ("command", &cc.command),
+        ("file", &cc.file),
+    ]
+    .into_iter()
+    .filter(|(_, value)| value.trim().is_empty())
+    .map(|(field, _)| ValidationError::EmptyField(field))
+    .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // 命令行无法按 shell 规则拆分
+    Split(shell::SplitError),
+    // 命令行为空
+    Empty,
+    // 找不到源文件
+    MissingSourceFile,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Split(err) => write!(f, "cannot split command: {}", err),
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::MissingSourceFile => write!(f, "no source file in command"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<shell::SplitError> for ParseError {
+    fn from(err: shell::SplitError) -> Self {
+        ParseError::Split(err)
+    }
+}
+
+// 拆分后的命令中编译器所在的范围: 跳过 --env-passthrough 写在命令前的环境变量,
+// zig cc / zig c++ 的子命令也算作编译器的一部分; 没有编译器时返回 None
+fn compiler_span(words: &[String]) -> Option<Range<usize>> {
+    let start = words
+        .iter()
+        .take_while(|word| shell::is_env_assignment(word))
+        .count();
+    let compiler = words.get(start)?;
+    let has_subcommand = is_zig(compiler)
+        && words
+            .get(start + 1)
+            .is_some_and(|word| word == "cc" || word == "c++");
+    Some(start..start + 1 + usize::from(has_subcommand))
+}
+
+// --strip-flag-pattern: 去掉与任一正则完全匹配的参数, 环境变量和编译器本身不参与匹配
+// 返回重新拼接的命令和被去掉的参数, 命令无法拆分时返回 None
+pub fn strip_flags(command: &str, patterns: &[Regex]) -> Option<(String, Vec<String>)> {
+    let words = shell::split_command(command).ok()?;
+    let span = compiler_span(&words)?;
+    let (stripped, kept): (Vec<&String>, Vec<&String>) = words[span.end..]
+        .iter()
+        .partition(|arg| patterns.iter().any(|pattern| pattern.is_match(arg)));
+    let words: Vec<&String> = words[..span.end].iter().chain(kept).collect();
+    Some((shell::join(&words), stripped.into_iter().cloned().collect()))
+}
+
+// --prepend-flag / --append-flag: 在编译器之后和命令末尾加上参数, 按参数拼接, 需要时加引号
+// 命令无法拆分时返回 None
+pub fn add_flags(command: &str, prepend: &[String], append: &[String]) -> Option<String> {
+    let words = shell::split_command(command).ok()?;
+    let span = compiler_span(&words)?;
+    let words: Vec<&String> = words[..span.end]
+        .iter()
+        .chain(prepend)
+        .chain(&words[span.end..])
+        .chain(append)
+        .collect();
+    Some(shell::join(&words))
+}
+
+// 按参数含义拆分后的编译命令
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedCompileCommand {
+    pub compiler: String,
+    pub source_file: String,
+    // -o 给出的目标文件; 没有 -o 时与编译器一样, 在当前目录生成与源文件同名的 .o
+    pub object_file: String,
+    pub include_paths: Vec<String>,
+    pub defines: Vec<String>,
+    // 其余参数按原顺序保存, 带值的参数 (如 -isystem dir) 保持相邻
+    pub other_flags: Vec<String>,
+}
+
+// 值作为下一个参数单独给出的选项
+pub const FLAGS_WITH_VALUE: &[&str] = &[
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-isysroot",
+    "-x",
+    "-arch",
+    "-target",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-Xclang",
+    "-Xlinker",
+    "-Xpreprocessor",
+    "-Xassembler",
+];
+
+// 命令拆分出的编译器、源文件、目标文件, 以及去掉 -c 和 -o <目标文件> 之后按原顺序保存的其余参数
+struct SplitCommand {
+    compiler: String,
+    source_file: String,
+    object_file: Option<String>,
+    flags: Vec<String>,
+}
+
+fn split_compile_command(cc: &CompileCommand) -> Result<SplitCommand, ParseError> {
+    let words = shell::split_command(&cc.command)?;
+    let span = compiler_span(&words).ok_or(ParseError::Empty)?;
+    let mut split = SplitCommand {
+        compiler: words[span.clone()].join(" "),
+        source_file: String::new(),
+        object_file: None,
+        flags: Vec::new(),
+    };
+    let mut args = words.into_iter().skip(span.end);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => {}
+            "-o" => split.object_file = args.next(),
+            flag if flag == "-I" || flag == "-D" || FLAGS_WITH_VALUE.contains(&flag) => {
+                split.flags.push(arg);
+                split.flags.extend(args.next());
+            }
+            _ if arg.starts_with("-o") => split.object_file = Some(arg[2..].to_string()),
+            _ if split.source_file.is_empty() && is_source_file(&arg, cc) => {
+                split.source_file = arg;
+            }
+            _ => split.flags.push(arg),
+        }
+    }
+
+    if split.source_file.is_empty() {
+        return Err(ParseError::MissingSourceFile);
+    }
+    Ok(split)
+}
+
+// 编译命令中的编译参数: 去掉环境变量、编译器、源文件、-c 和 -o <目标文件>,
+// 其余参数保持原来的顺序, -I、-D、-U 之间的先后关系会影响结果
+pub fn compile_flags(cc: &CompileCommand) -> Result<Vec<String>, ParseError> {
+    Ok(split_compile_command(cc)?.flags)
+}
+
+impl ParsedCompileCommand {
+    // 目前只有测试使用, 作为以后规范化参数的基础
+    #[allow(dead_code)]
+    pub fn from_compile_command(cc: &CompileCommand) -> Result<ParsedCompileCommand, ParseError> {
+        let split = split_compile_command(cc)?;
+        let object_file = split.object_file.unwrap_or_else(|| {
+            let stem = Path::new(&split.source_file)
+                .file_stem()
+                .unwrap_or_default();
+            format!("{}.o", stem.to_string_lossy())
+        });
+        let mut parsed = ParsedCompileCommand {
+            compiler: split.compiler,
+            source_file: split.source_file,
+            object_file,
+            ..Default::default()
+        };
+        let mut flags = split.flags.into_iter();
+
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "-I" => parsed.include_paths.extend(flags.next()),
+                "-D" => parsed.defines.extend(flags.next()),
+                name if FLAGS_WITH_VALUE.contains(&name) => {
+                    parsed.other_flags.push(flag);
+                    parsed.other_flags.extend(flags.next());
+                }
+                _ if flag.starts_with("-I") => parsed.include_paths.push(flag[2..].to_string()),
+                _ if flag.starts_with("-D") => parsed.defines.push(flag[2..].to_string()),
+                _ => parsed.other_flags.push(flag),
+            }
+        }
+        Ok(parsed)
+    }
+
+    // 按 编译器、-I、-D、其余参数、-c 源文件、-o 目标文件 的顺序拼成命令, 需要时加引号
+    pub fn to_command(&self) -> String {
+        let mut words: Vec<String> = match self.compiler.split_once(' ') {
+            Some((zig, subcommand)) if is_zig(zig) => vec![zig.to_string(), subcommand.to_string()],
+            _ => vec![self.compiler.clone()],
+        };
+        words.extend(self.include_paths.iter().map(|path| format!("-I{}", path)));
+        words.extend(self.defines.iter().map(|define| format!("-D{}", define)));
+        words.extend(self.other_flags.iter().cloned());
+        words.extend(["-c".to_string(), self.source_file.clone()]);
+        if !self.object_file.is_empty() {
+            words.extend(["-o".to_string(), self.object_file.clone()]);
+        }
+        shell::join(&words)
+    }
+}
+
+// 逐项构造编译命令, 如按 compile_flags.txt 为源文件合成条目
+// 没有给出目标文件时命令中不写 -o
+#[derive(Debug, Clone, Default)]
+pub struct CompileCommandBuilder {
+    parsed: ParsedCompileCommand,
+}
+
+impl CompileCommandBuilder {
+    pub fn new(compiler: &str, source_file: &str) -> CompileCommandBuilder {
+        CompileCommandBuilder {
+            parsed: ParsedCompileCommand {
+                compiler: compiler.to_string(),
+                source_file: source_file.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    // 以下三个目前只有测试使用
+    #[allow(dead_code)]
+    pub fn object_file(mut self, path: &str) -> CompileCommandBuilder {
+        self.parsed.object_file = path.to_string();
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn include_path(mut self, path: &str) -> CompileCommandBuilder {
+        self.parsed.include_paths.push(path.to_string());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn define(mut self, define: &str) -> CompileCommandBuilder {
+        self.parsed.defines.push(define.to_string());
+        self
+    }
+
+    // 其余参数按给出的顺序放在 -I 和 -D 之后
+    pub fn flags<S: AsRef<str>>(mut self, flags: &[S]) -> CompileCommandBuilder {
+        let flags = flags.iter().map(|flag| flag.as_ref().to_string());
+        self.parsed.other_flags.extend(flags);
+        self
+    }
+
+    pub fn build(self, directory: &str) -> CompileCommand {
+        CompileCommand {
+            directory: directory.to_string(),
+            command: self.parsed.to_command(),
+            file: self.parsed.source_file,
+            compiler: None,
+            headers: None,
+        }
+    }
+}
+
+fn is_zig(compiler: &str) -> bool {
+    Path::new(compiler)
+        .file_name()
+        .is_some_and(|name| name == "zig")
+}
+
+// 和记录的源文件相同, 或者是带源文件扩展名的非选项参数
+// file 可能已解析为绝对路径, 所以也和按 directory 拼接后的路径比较
+fn is_source_file(arg: &str, cc: &CompileCommand) -> bool {
+    if arg == cc.file || Path::new(&cc.directory).join(arg) == Path::new(&cc.file) {
+        return true;
+    }
+    !arg.starts_with('-')
+        && Path::new(arg).extension().is_some_and(|ext| {
+            DEFAULT_SOURCE_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn full_match(patterns: &[&str]) -> Vec<Regex> {
+        patterns
+            .iter()
+            .map(|pattern| Regex::new(&format!("^(?:{})$", pattern)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn add_flags_to_plain_command() {
+        let command = add_flags(
+            "gcc -c a.c -o a.o",
+            &strings(&["-ferror-limit=0"]),
+            &strings(&["-Igen"]),
+        );
+        assert_eq!(
+            command.as_deref(),
+            Some("gcc -ferror-limit=0 -c a.c -o a.o -Igen")
+        );
+    }
+
+    #[test]
+    fn add_flags_after_zig_subcommand() {
+        let command = add_flags("zig cc -c a.c -o a.o", &strings(&["-ferror-limit=0"]), &[]);
+        assert_eq!(
+            command.as_deref(),
+            Some("zig cc -ferror-limit=0 -c a.c -o a.o")
+        );
+    }
+
+    #[test]
+    fn add_flags_after_env_assignments() {
+        let command = add_flags(
+            "CCACHE_DISABLE=1 LANG=C gcc -c a.c",
+            &strings(&["--sysroot=/my root"]),
+            &[],
+        );
+        assert_eq!(
+            command.as_deref(),
+            Some("CCACHE_DISABLE=1 LANG=C gcc '--sysroot=/my root' -c a.c")
+        );
+    }
+
+    #[test]
+    fn strip_flags_from_plain_command() {
+        let (command, stripped) = strip_flags(
+            "gcc -fsanitize=address -O2 -pg -c a.c -o a.o",
+            &full_match(&["-fsanitize=.*", "-pg"]),
+        )
+        .unwrap();
+        assert_eq!(command, "gcc -O2 -c a.c -o a.o");
+        assert_eq!(stripped, strings(&["-fsanitize=address", "-pg"]));
+    }
+
+    #[test]
+    fn strip_flags_keeps_zig_subcommand() {
+        let (command, stripped) =
+            strip_flags("zig cc -pg -c a.c", &full_match(&["cc", "-pg"])).unwrap();
+        assert_eq!(command, "zig cc -c a.c");
+        assert_eq!(stripped, strings(&["-pg"]));
+    }
+
+    #[test]
+    fn strip_flags_keeps_env_assignments() {
+        let (command, stripped) = strip_flags(
+            "LANG=C gcc --coverage -c a.c",
+            &full_match(&["LANG=.*", "--coverage"]),
+        )
+        .unwrap();
+        assert_eq!(command, "LANG=C gcc -c a.c");
+        assert_eq!(stripped, strings(&["--coverage"]));
+    }
+
+    fn entry(directory: &str, command: &str, file: &str) -> CompileCommand {
+        CompileCommand {
+            directory: directory.to_string(),
+            command: command.to_string(),
+            file: file.to_string(),
+            compiler: None,
+            headers: None,
+        }
+    }
+
+    #[test]
+    fn parse_splits_command_by_meaning() {
+        let cc = entry(
+            "/src",
+            "gcc -Iinclude -I /opt/inc -DFOO -D BAR=1 -isystem sys -O2 -c a.c -o a.o",
+            "a.c",
+        );
+        let parsed = ParsedCompileCommand::from_compile_command(&cc).unwrap();
+        assert_eq!(parsed.compiler, "gcc");
+        assert_eq!(parsed.source_file, "a.c");
+        assert_eq!(parsed.include_paths, strings(&["include", "/opt/inc"]));
+        assert_eq!(parsed.defines, strings(&["FOO", "BAR=1"]));
+        assert_eq!(parsed.other_flags, strings(&["-isystem", "sys", "-O2"]));
+    }
+
+    #[test]
+    fn parse_drops_attached_object_file() {
+        let cc = entry("/src", "clang -c a.c -oa.o -g", "a.c");
+        let parsed = ParsedCompileCommand::from_compile_command(&cc).unwrap();
+        assert_eq!(parsed.object_file, "a.o");
+        assert_eq!(parsed.other_flags, strings(&["-g"]));
+    }
+
+    #[test]
+    fn parse_takes_object_file_from_output_or_source_stem() {
+        let object_of = |command| {
+            let cc = entry("/src", command, "lib/a.c");
+            ParsedCompileCommand::from_compile_command(&cc)
+                .unwrap()
+                .object_file
+        };
+        assert_eq!(object_of("gcc -c lib/a.c -o build/a.c.o"), "build/a.c.o");
+        assert_eq!(object_of("gcc -o build/a.o -c lib/a.c"), "build/a.o");
+        // 没有 -o 时编译器在当前目录生成 a.o
+        assert_eq!(object_of("gcc -O2 -c lib/a.c"), "a.o");
+    }
+
+    #[test]
+    fn builder_writes_fields_in_order() {
+        let cc = CompileCommandBuilder::new("gcc", "src/a.c")
+            .flags(&["-O2", "-isystem", "my dir"])
+            .define("X=1")
+            .include_path("inc")
+            .object_file("a.o")
+            .build("/src");
+        assert_eq!(cc.directory, "/src");
+        assert_eq!(cc.file, "src/a.c");
+        assert_eq!(
+            cc.command,
+            "gcc -Iinc -DX=1 -O2 -isystem 'my dir' -c src/a.c -o a.o"
+        );
+        // 没有目标文件时不写 -o
+        let cc = CompileCommandBuilder::new("cc", "a.c").build("/src");
+        assert_eq!(cc.command, "cc -c a.c");
+    }
+
+    #[test]
+    fn builder_output_parses_back_to_the_same_fields() {
+        let cc = CompileCommandBuilder::new("zig c++", "a.cpp")
+            .include_path("/opt/inc")
+            .define("NDEBUG")
+            .flags(&["-std=c++17", "-x", "c++"])
+            .object_file("out/a.o")
+            .build("/src");
+        assert_eq!(
+            cc.command,
+            "zig c++ -I/opt/inc -DNDEBUG -std=c++17 -x c++ -c a.cpp -o out/a.o"
+        );
+        let parsed = ParsedCompileCommand::from_compile_command(&cc).unwrap();
+        assert_eq!(
+            parsed,
+            ParsedCompileCommand {
+                compiler: "zig c++".to_string(),
+                source_file: "a.cpp".to_string(),
+                object_file: "out/a.o".to_string(),
+                include_paths: strings(&["/opt/inc"]),
+                defines: strings(&["NDEBUG"]),
+                other_flags: strings(&["-std=c++17", "-x", "c++"]),
+            }
+        );
+        assert_eq!(parsed.to_command(), cc.command);
+    }
+
+    #[test]
+    fn parse_matches_source_resolved_to_absolute_path() {
+        let cc = entry("/src", "gcc -c lib/x.inc -o x.o", "/src/lib/x.inc");
+        let parsed = ParsedCompileCommand::from_compile_command(&cc).unwrap();
+        assert_eq!(parsed.source_file, "lib/x.inc");
+    }
+
+    #[test]
+    fn parse_keeps_zig_subcommand_and_skips_env() {
+        let cc = entry("/src", "LANG=C zig c++ -c a.cpp -o a.o", "a.cpp");
+        let parsed = ParsedCompileCommand::from_compile_command(&cc).unwrap();
+        assert_eq!(parsed.compiler, "zig c++");
+        assert_eq!(parsed.source_file, "a.cpp");
+        assert!(parsed.other_flags.is_empty());
+    }
+
+    #[test]
+    fn parse_reports_errors() {
+        let parse =
+            |command| ParsedCompileCommand::from_compile_command(&entry("/src", command, "a.c"));
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("gcc -c -o a.o"), Err(ParseError::MissingSourceFile));
+        assert!(matches!(parse("gcc -c 'a.c"), Err(ParseError::Split(_))));
+    }
+
+    #[test]
+    fn compile_flags_keep_their_order() {
+        let cc = entry(
+            "/src",
+            "LANG=C gcc -UFOO -DFOO=2 -isystem sys -Iinc -c a.c -o a.o -I other -O2",
+            "a.c",
+        );
+        assert_eq!(
+            compile_flags(&cc).unwrap(),
+            strings(&["-UFOO", "-DFOO=2", "-isystem", "sys", "-Iinc", "-I", "other", "-O2"])
+        );
+        assert_eq!(
+            compile_flags(&entry("/src", "gcc -c", "a.c")),
+            Err(ParseError::MissingSourceFile)
+        );
+    }
+
+    #[test]
+    fn flags_of_unsplittable_command_are_left_alone() {
+        assert_eq!(add_flags("gcc -c 'a.c", &strings(&["-g"]), &[]), None);
+        assert!(strip_flags("gcc -c 'a.c", &full_match(&["-g"])).is_none());
+    }
+
+    #[test]
+    fn validate_accepts_complete_entry() {
+        assert!(validate_entry(&entry("/src", "gcc -c a.c", "a.c")).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_each_empty_field() {
+        assert_eq!(
+            validate_entry(&entry("", "gcc -c a.c", "a.c")),
+            vec![ValidationError::EmptyField("directory")]
+        );
+        assert_eq!(
+            validate_entry(&entry("/src", "gcc -c ''", "")),
+            vec![ValidationError::EmptyField("file")]
+        );
+        // 只有空白也算空
+        assert_eq!(
+            validate_entry(&entry(" ", "  ", "")),
+            vec![
+                ValidationError::EmptyField("directory"),
+                ValidationError::EmptyField("command"),
+                ValidationError::EmptyField("file"),
+            ]
+        );
+        assert_eq!(
+            ValidationError::EmptyField("file").to_string(),
+            "`file` is empty"
+        );
+    }
+
+    #[test]
+    fn fields_are_serialized_in_a_fixed_order() {
+        let mut cc = entry("/src", "cc -c a.c", "a.c");
+        assert_eq!(
+            serde_json::to_string(&cc).unwrap(),
+            r#"{"directory":"/src","file":"a.c","command":"cc -c a.c"}"#
+        );
+        cc.headers = Some(vec!["a.h".to_string()]);
+        cc.compiler = Some("cc".to_string());
+        assert_eq!(
+            serde_json::to_string(&cc).unwrap(),
+            r#"{"directory":"/src","file":"a.c","command":"cc -c a.c","compiler":"cc","headers":["a.h"]}"#
+        );
+        let pretty = serde_json::to_string_pretty(&cc).unwrap();
+        let keys: Vec<&str> = pretty
+            .lines()
+            .filter_map(|line| Some(line.trim_start().strip_prefix('"')?.split_once("\":")?.0))
+            .collect();
+        assert_eq!(
+            keys,
+            ["directory", "file", "command", "compiler", "headers"]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+
+// 默认的配置文件名, 依次在输出目录和当前目录中查找
+pub const CONFIG_FILE_NAME: &str = ".bear_rs.toml";
+
+// 配置文件 .bear_rs.toml 的内容, 每一项对应一个命令行参数, 命令行给出时以命令行为准
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub compilers: Option<Vec<String>>,
+    pub compiler_regex: Option<Vec<String>>,
+    pub extensions: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub exclude_dir_pattern: Option<Vec<String>>,
+    pub format: Option<String>,
+    pub compact: Option<bool>,
+    pub assume_jobserver: Option<bool>,
+    pub absolute_paths: Option<bool>,
+}
+
+impl Config {
+    // 读取配置文件: 显式指定的文件必须存在, 否则查找默认位置, 都没有时使用空配置
+    pub async fn load(
+        explicit: Option<&str>,
+        disabled: bool,
+        output_dir: &str,
+    ) -> Result<Config, String> {
+        if disabled {
+            return Ok(Config::default());
+        }
+        let path = match explicit {
+            Some(path) => PathBuf::from(path),
+            None => match find_config(output_dir) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|err| format!("无法读取配置文件 {}: {}", path.display(), err))?;
+        toml::from_str(&content)
+            .map_err(|err| format!("配置文件 {} 格式错误: {}", path.display(), err))
+    }
+}
+
+fn find_config(output_dir: &str) -> Option<PathBuf> {
+    [Path::new(output_dir), Path::new(".")]
+        .into_iter()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// 参数是否在命令行中显式给出 (而不是默认值)
+fn from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+// 命令行给出的开关优先, 否则使用配置文件的值
+pub fn merged_flag(matches: &ArgMatches, id: &str, configured: Option<bool>) -> bool {
+    if from_command_line(matches, id) {
+        return matches.get_flag(id);
+    }
+    configured.unwrap_or(false)
+}
+
+// 命令行给出的值优先, 否则依次使用配置文件的值和参数默认值
+pub fn merged_value(matches: &ArgMatches, id: &str, configured: &Option<String>) -> String {
+    match configured {
+        Some(value) if !from_command_line(matches, id) => value.clone(),
+        _ => matches.get_one::<String>(id).cloned().unwrap_or_default(),
+    }
+}
+
+// 可重复参数: 命令行给出时整体替换配置文件中的列表
+pub fn merged_list(
+    matches: &ArgMatches,
+    id: &str,
+    configured: &Option<Vec<String>>,
+) -> Vec<String> {
+    match configured {
+        Some(values) if !from_command_line(matches, id) => values.clone(),
+        _ => matches
+            .get_many::<String>(id)
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+    }
+}
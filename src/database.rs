@@ -0,0 +1,605 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::compile_command::CompileCommand;
+use crate::paths;
+use crate::shell;
+
+// 其他工具生成的数据库可能用 arguments 代替 command
+#[derive(Deserialize)]
+struct RawEntry {
+    directory: String,
+    file: String,
+    command: Option<String>,
+    arguments: Option<Vec<String>>,
+}
+
+impl RawEntry {
+    fn into_compile_command(self) -> io::Result<CompileCommand> {
+        let command = match (self.command, self.arguments) {
+            (Some(command), _) => command,
+            (None, Some(arguments)) => shell::join(&arguments),
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("entry for {} has neither command nor arguments", self.file),
+                ))
+            }
+        };
+        Ok(CompileCommand {
+            directory: self.directory,
+            command,
+            file: self.file,
+            compiler: None,
+            headers: None,
+        })
+    }
+}
+
+// 读取已有的 compile_commands.json, 也接受 --format jsonl 写出的每行一个对象
+pub fn load(path: &Path) -> io::Result<Vec<CompileCommand>> {
+    let raw: Vec<RawEntry> = read_entries(path)?;
+    raw.into_iter()
+        .map(RawEntry::into_compile_command)
+        .collect()
+}
+
+// 按 JSON 数组或每行一个对象读取文件中的所有条目
+fn read_entries<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let content = std::fs::read_to_string(path)?;
+    let invalid = |err: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+    if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content).map_err(invalid)
+    } else {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(invalid)
+    }
+}
+
+// diff 子命令中命令有变化的源文件, 以及按参数比较时去掉和加上的参数
+pub struct Changed {
+    pub file: String,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+// 两个数据库的差异, 源文件都是按 directory 解析后的绝对路径
+#[derive(Default)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<Changed>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// 按 (directory, file) 比较两个数据库, 命令拆分成参数后比较,
+// 所以 command 和 arguments 两种写法、引号写法不同的命令视为相同
+// 同一个源文件有多条时取最后一条, 与 find 一致
+pub fn diff(old: &[CompileCommand], new: &[CompileCommand]) -> Diff {
+    let old = index_by_source(old);
+    let new = index_by_source(new);
+    let mut diff = Diff::default();
+    for (key, old_args) in &old {
+        match new.get(key) {
+            None => diff.removed.push(key.1.clone()),
+            Some(new_args) if new_args != old_args => diff.changed.push(Changed {
+                file: key.1.clone(),
+                removed: missing_from(old_args, new_args),
+                added: missing_from(new_args, old_args),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff.added = new
+        .keys()
+        .filter(|key| !old.contains_key(*key))
+        .map(|(_, file)| file.clone())
+        .collect();
+    diff
+}
+
+fn index_by_source(entries: &[CompileCommand]) -> BTreeMap<(String, String), Vec<String>> {
+    entries
+        .iter()
+        .map(|entry| {
+            // 无法拆分的命令按空白拆开, 仍然可以比较
+            let args = shell::split_command(&entry.command).unwrap_or_else(|_| {
+                entry
+                    .command
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect()
+            });
+            (source_key(entry), args)
+        })
+        .collect()
+}
+
+// 在 args 中但不在 other 中的参数, 按出现次数计算, 保持 args 中的顺序
+fn missing_from(args: &[String], other: &[String]) -> Vec<String> {
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for arg in other {
+        *remaining.entry(arg).or_default() += 1;
+    }
+    args.iter()
+        .filter(|arg| match remaining.get_mut(arg.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+// 逐个读取数据库中的条目交给 f, 不把整个文件读入内存, 用于合并很大的数据库
+// 与 load 一样接受 JSON 数组和每行一个对象两种格式
+pub fn for_each_entry(path: &Path, mut f: impl FnMut(CompileCommand)) -> io::Result<()> {
+    let invalid = |err: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut reader = BufReader::new(File::open(path)?);
+    // 跳过开头的空白, 看第一个字符是不是 [
+    let is_array = loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.iter().position(|byte| !byte.is_ascii_whitespace()) {
+            Some(start) => break buffer[start] == b'[',
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    };
+    if is_array {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        deserializer
+            .deserialize_seq(EachEntry(&mut f))
+            .map_err(invalid)?;
+        return deserializer.end().map_err(invalid);
+    }
+    for raw in serde_json::Deserializer::from_reader(reader).into_iter::<RawEntry>() {
+        f(raw.map_err(invalid)?.into_compile_command()?);
+    }
+    Ok(())
+}
+
+// merge 子命令: 读两遍输入, 第一遍只记下每个源文件由哪个输入的第几个条目提供,
+// 第二遍按输入的顺序交出这些条目, 所以不需要把所有条目同时放在内存中
+pub struct Merge {
+    inputs: Vec<PathBuf>,
+    winners: HashMap<(String, String), (usize, usize)>,
+}
+
+impl Merge {
+    // 同一个源文件出现多次时默认后面的输入优先, keep_first 时前面的优先
+    pub fn scan(inputs: Vec<PathBuf>, keep_first: bool) -> io::Result<Merge> {
+        let mut winners = HashMap::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let mut position = 0;
+            for_each_entry(input, |entry| {
+                let key = source_key(&entry);
+                if keep_first {
+                    winners.entry(key).or_insert((index, position));
+                } else {
+                    winners.insert(key, (index, position));
+                }
+                position += 1;
+            })
+            .map_err(|err| with_path(input, err))?;
+        }
+        Ok(Merge { inputs, winners })
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // 把合并后保留的条目逐个交给 f
+    pub fn for_each_entry(&self, mut f: impl FnMut(CompileCommand)) -> io::Result<()> {
+        for (index, input) in self.inputs.iter().enumerate() {
+            let mut position = 0;
+            for_each_entry(input, |entry| {
+                if self.winners.get(&source_key(&entry)) == Some(&(index, position)) {
+                    f(entry);
+                }
+                position += 1;
+            })
+            .map_err(|err| with_path(input, err))?;
+        }
+        Ok(())
+    }
+}
+
+// 在错误信息前加上出错的文件
+fn with_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+// 逐个反序列化 JSON 数组中的元素
+struct EachEntry<F>(F);
+
+impl<'de, F: FnMut(CompileCommand)> Visitor<'de> for EachEntry<F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of compile commands")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(raw) = seq.next_element::<RawEntry>()? {
+            let entry = raw.into_compile_command().map_err(de::Error::custom)?;
+            (self.0)(entry);
+        }
+        Ok(())
+    }
+}
+
+// 条目对应的源文件: 规范化的 directory 和按它解析成绝对路径的 file
+pub fn source_key(entry: &CompileCommand) -> (String, String) {
+    let directory = paths::normalize_lexically(Path::new(&entry.directory))
+        .to_string_lossy()
+        .to_string();
+    (
+        directory,
+        paths::absolute_lexical(&entry.directory, &entry.file),
+    )
+}
+
+// check 子命令发现的一个问题, index 是条目在文件中的序号 (从 0 开始)
+pub struct Problem {
+    pub index: usize,
+    // 重复的条目只是警告, 不影响退出状态
+    pub warning: bool,
+    pub message: String,
+}
+
+// 检查数据库中的每个条目: 必需的 directory、file 以及 command 或 arguments;
+// strict 时还要求 directory 存在、file 能解析到存在的文件
+// (directory, file) 相同的条目作为警告报告
+pub fn check(path: &Path, strict: bool) -> io::Result<Vec<Problem>> {
+    let entries: Vec<Value> = read_entries(path)?;
+    let mut problems = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let mut error = |message: String| {
+            problems.push(Problem {
+                index,
+                warning: false,
+                message,
+            })
+        };
+        let Some(entry) = entry.as_object() else {
+            error("不是 JSON 对象".to_string());
+            continue;
+        };
+        let text = |field: &str| {
+            entry
+                .get(field)
+                .and_then(Value::as_str)
+                .filter(|value| !value.trim().is_empty())
+        };
+        let directory = text("directory");
+        let file = text("file");
+        if directory.is_none() {
+            error("缺少 directory 或者不是非空字符串".to_string());
+        }
+        if file.is_none() {
+            error("缺少 file 或者不是非空字符串".to_string());
+        }
+        let has_arguments = entry
+            .get("arguments")
+            .and_then(Value::as_array)
+            .is_some_and(|arguments| {
+                !arguments.is_empty() && arguments.iter().all(Value::is_string)
+            });
+        if text("command").is_none() && !has_arguments {
+            error("缺少 command 或 arguments".to_string());
+        }
+        let (Some(directory), Some(file)) = (directory, file) else {
+            continue;
+        };
+        if strict {
+            if !Path::new(directory).is_dir() {
+                error(format!("directory {} 不存在", directory));
+            } else if !Path::new(directory).join(file).exists() {
+                error(format!("file {} 不存在", file));
+            }
+        }
+        let key = (directory.to_string(), file.to_string());
+        if let Some(first) = seen.get(&key) {
+            problems.push(Problem {
+                index,
+                warning: true,
+                message: format!("与条目 {} 的 directory 和 file 相同", first),
+            });
+        } else {
+            seen.insert(key, index);
+        }
+    }
+    Ok(problems)
+}
+
+// 找出源文件对应的条目: 先按原样比较, 再比较两边解析成绝对路径后的结果
+// 同一个源文件有多条时取最后一条, 与 SQLite 输出的行为一致
+pub fn find<'a>(entries: &'a [CompileCommand], file: &str) -> Option<&'a CompileCommand> {
+    if let Some(entry) = entries.iter().rev().find(|entry| entry.file == file) {
+        return Some(entry);
+    }
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let wanted = absolute(&current_dir.to_string_lossy(), file);
+    entries
+        .iter()
+        .rev()
+        .find(|entry| absolute(&entry.directory, &entry.file) == wanted)
+}
+
+// 文件存在时用真实路径, 不存在时按字面拼接
+fn absolute(directory: &str, file: &str) -> PathBuf {
+    match paths::resolve_source(directory, file) {
+        Ok(resolved) => PathBuf::from(resolved),
+        Err(_) => PathBuf::from(paths::absolute_lexical(directory, file)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn files(entries: &[CompileCommand]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.file.as_str()).collect()
+    }
+
+    #[test]
+    fn load_accepts_arrays_lines_and_arguments() {
+        let dir = TempDir::new();
+        let array = dir.write(
+            "array.json",
+            r#"[
+                {"directory": "/src", "file": "a.c", "command": "cc -c a.c"},
+                {"directory": "/src", "file": "b c.c", "arguments": ["cc", "-c", "b c.c"]}
+            ]"#,
+        );
+        let entries = load(&array).unwrap();
+        assert_eq!(files(&entries), ["a.c", "b c.c"]);
+        assert_eq!(entries[1].command, "cc -c 'b c.c'");
+
+        let lines = dir.write(
+            "lines.jsonl",
+            "{\"directory\": \"/src\", \"file\": \"a.c\", \"command\": \"cc -c a.c\"}\n\n",
+        );
+        assert_eq!(files(&load(&lines).unwrap()), ["a.c"]);
+
+        let missing = dir.write("missing.json", r#"[{"directory": "/src", "file": "a.c"}]"#);
+        assert_eq!(
+            load(&missing).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn query_finds_the_entry_of_a_source() {
+        let dir = TempDir::new();
+        dir.write("src/a.c", "");
+        let root = dir.path().to_string_lossy().to_string();
+        let entry = |directory: &str, file: &str, command: &str| CompileCommand {
+            directory: directory.to_string(),
+            command: command.to_string(),
+            file: file.to_string(),
+            compiler: None,
+            headers: None,
+        };
+        let entries = [
+            entry(&root, "src/a.c", "cc -O0 -c src/a.c"),
+            entry("/elsewhere", "b.c", "cc -c b.c"),
+            entry(&format!("{}/src", root), "a.c", "cc -O2 -c a.c"),
+        ];
+
+        // 按原样比较, 同一个源文件有多条时取最后一条
+        assert_eq!(find(&entries, "b.c").unwrap().command, "cc -c b.c");
+        // 按 directory 解析后比较
+        let found = find(&entries, &format!("{}/src/a.c", root)).unwrap();
+        assert_eq!(found.command, "cc -O2 -c a.c");
+        assert_eq!(
+            find(&entries, "/elsewhere/b.c").unwrap().command,
+            "cc -c b.c"
+        );
+        assert!(find(&entries, "/elsewhere/c.c").is_none());
+        assert!(find(&entries, "c.c").is_none());
+    }
+
+    fn problems(path: &Path, strict: bool) -> Vec<(usize, bool, String)> {
+        check(path, strict)
+            .unwrap()
+            .into_iter()
+            .map(|problem| (problem.index, problem.warning, problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn check_reports_invalid_entries_by_index() {
+        let dir = TempDir::new();
+        let database = dir.write(
+            "compile_commands.json",
+            r#"[
+                {"directory": "/src", "file": "a.c", "command": "cc -c a.c"},
+                "cc -c b.c",
+                {"file": "c.c", "command": "cc -c c.c"},
+                {"directory": "/src", "file": " ", "arguments": ["cc", "-c", "d.c"]},
+                {"directory": "/src", "file": "e.c", "arguments": ["cc", 1]},
+                {"directory": "/src", "file": "a.c", "arguments": ["cc", "-c", "a.c"]}
+            ]"#,
+        );
+        assert_eq!(
+            problems(&database, false),
+            [
+                (1, false, "不是 JSON 对象".to_string()),
+                (2, false, "缺少 directory 或者不是非空字符串".to_string()),
+                (3, false, "缺少 file 或者不是非空字符串".to_string()),
+                (4, false, "缺少 command 或 arguments".to_string()),
+                (5, true, "与条目 0 的 directory 和 file 相同".to_string()),
+            ]
+        );
+
+        let broken = dir.write("broken.json", "[{\"directory\": ");
+        assert!(check(&broken, false).is_err());
+    }
+
+    #[test]
+    fn strict_check_requires_existing_directory_and_file() {
+        let dir = TempDir::new();
+        dir.write("src/a.c", "");
+        let root = dir.path().to_string_lossy().to_string();
+        let entries = serde_json::json!([
+            {"directory": root, "file": "src/a.c", "command": "cc -c src/a.c"},
+            {"directory": root, "file": "src/missing.c", "command": "cc -c src/missing.c"},
+            {"directory": format!("{}/none", root), "file": "a.c", "command": "cc -c a.c"},
+        ]);
+        let database = dir.write("compile_commands.json", &entries.to_string());
+
+        assert!(problems(&database, false).is_empty());
+        assert_eq!(
+            problems(&database, true),
+            [
+                (1, false, "file src/missing.c 不存在".to_string()),
+                (2, false, format!("directory {}/none 不存在", root)),
+            ]
+        );
+    }
+
+    fn entry(directory: &str, file: &str, command: &str) -> CompileCommand {
+        CompileCommand {
+            directory: directory.to_string(),
+            command: command.to_string(),
+            file: file.to_string(),
+            compiler: None,
+            headers: None,
+        }
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed_entries() {
+        let old = [
+            entry("/src", "a.c", "cc -O2 -c a.c"),
+            entry("/src", "b.c", "cc -c b.c"),
+            entry("/src", "c.c", "cc -c 'c.c'"),
+        ];
+        let new = [
+            entry("/src/.", "c.c", "cc -c c.c"),
+            entry("/src", "a.c", "cc -O3 -c a.c -DX"),
+            entry("/src", "d.c", "cc -c d.c"),
+        ];
+        let diff = diff(&old, &new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added, ["/src/d.c"]);
+        assert_eq!(diff.removed, ["/src/b.c"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].file, "/src/a.c");
+        assert_eq!(diff.changed[0].removed, ["-O2"]);
+        assert_eq!(diff.changed[0].added, ["-O3", "-DX"]);
+    }
+
+    #[test]
+    fn diff_of_identical_databases_is_empty() {
+        let dir = TempDir::new();
+        // command 和 arguments 两种写法视为相同
+        let old = dir.write(
+            "old.json",
+            r#"[{"directory": "/src", "file": "a c.c", "command": "cc -c \"a c.c\""}]"#,
+        );
+        let new = dir.write(
+            "new.json",
+            r#"[{"directory": "/src", "file": "a c.c", "arguments": ["cc", "-c", "a c.c"]}]"#,
+        );
+        assert!(diff(&load(&old).unwrap(), &load(&new).unwrap()).is_empty());
+    }
+
+    fn merged(inputs: &[PathBuf], keep_first: bool) -> Vec<(String, String)> {
+        let merge = Merge::scan(inputs.to_vec(), keep_first).unwrap();
+        assert_eq!(merge.inputs(), inputs.len());
+        let mut entries = Vec::new();
+        merge
+            .for_each_entry(|entry| entries.push((entry.file, entry.command)))
+            .unwrap();
+        entries
+    }
+
+    fn merge_inputs(dir: &TempDir) -> Vec<PathBuf> {
+        let first = dir.write(
+            "a/compile_commands.json",
+            r#"[
+                {"directory": "/src", "file": "x.c", "command": "cc -O0 -c x.c"},
+                {"directory": "/src", "file": "y.c", "command": "cc -c y.c"},
+                {"directory": "/src", "file": "y.c", "command": "cc -g -c y.c"}
+            ]"#,
+        );
+        let second = dir.write(
+            "b/compile_commands.jsonl",
+            concat!(
+                r#"{"directory": "/src", "file": "/src/x.c", "arguments": ["cc", "-O2", "-c", "x.c"]}"#,
+                "\n",
+                r#"{"directory": "/src", "file": "z.c", "command": "cc -c z.c"}"#,
+                "\n",
+            ),
+        );
+        vec![first, second]
+    }
+
+    #[test]
+    fn merge_lets_later_inputs_win() {
+        let dir = TempDir::new();
+        let entries = merged(&merge_inputs(&dir), false);
+        let expected = [
+            ("y.c", "cc -g -c y.c"),
+            ("/src/x.c", "cc -O2 -c x.c"),
+            ("z.c", "cc -c z.c"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(file, command)| (file.to_string(), command.to_string()))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn merge_keep_first_lets_earlier_entries_win() {
+        let dir = TempDir::new();
+        let entries = merged(&merge_inputs(&dir), true);
+        let files: Vec<&str> = entries.iter().map(|(file, _)| file.as_str()).collect();
+        assert_eq!(files, ["x.c", "y.c", "z.c"]);
+        assert_eq!(entries[0].1, "cc -O0 -c x.c");
+        assert_eq!(entries[1].1, "cc -c y.c");
+    }
+
+    #[test]
+    fn merge_names_the_input_it_cannot_read() {
+        let dir = TempDir::new();
+        let broken = dir.write("broken.json", "[{");
+        let Err(err) = Merge::scan(vec![broken.clone()], false) else {
+            panic!("broken input was merged");
+        };
+        assert!(
+            err.to_string().starts_with(&broken.display().to_string()),
+            "{}",
+            err
+        );
+    }
+}
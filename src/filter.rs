@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+
+use crate::paths;
+
+// Windows 的文件系统不区分大小写, 其他平台按原样匹配
+const CASE_INSENSITIVE_GLOBS: bool = cfg!(windows);
+
+fn build_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern)
+        .case_insensitive(CASE_INSENSITIVE_GLOBS)
+        // `*` 不跨目录, 跨目录用 `**`
+        .literal_separator(true)
+        .build()
+}
+
+// 检查命令行给出的 glob 是否合法
+pub fn parse_glob(pattern: &str) -> Result<String, String> {
+    build_glob(pattern)
+        .map(|_| pattern.to_string())
+        .map_err(|err| format!("invalid glob {:?}: {}", pattern, err))
+}
+
+// --exclude 给出的一个模式及其排除的条目数
+struct ExcludePattern {
+    pattern: String,
+    matcher: GlobMatcher,
+    excluded: usize,
+}
+
+// 按源文件路径排除条目, 源文件相对 directory 的路径或绝对路径匹配任一模式即排除
+#[derive(Default)]
+pub struct ExcludeFilter {
+    patterns: Vec<ExcludePattern>,
+}
+
+impl ExcludeFilter {
+    pub fn new(patterns: &[String]) -> Result<ExcludeFilter, String> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let glob = build_glob(pattern)
+                    .map_err(|err| format!("invalid glob {:?}: {}", pattern, err))?;
+                Ok(ExcludePattern {
+                    pattern: pattern.clone(),
+                    matcher: glob.compile_matcher(),
+                    excluded: 0,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(ExcludeFilter { patterns })
+    }
+
+    // 条目是否被排除, 同时计入第一个匹配的模式
+    pub fn excludes(&mut self, directory: &str, file: &str) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let absolute = paths::absolute_lexical(directory, file);
+        let relative = Path::new(&absolute)
+            .strip_prefix(paths::normalize_lexically(Path::new(directory)))
+            .ok()
+            .map(|relative| relative.to_string_lossy().to_string());
+        let candidates: Vec<&str> = std::iter::once(absolute.as_str())
+            .chain(relative.as_deref())
+            .collect();
+        self.matches_any(&candidates)
+    }
+
+    // --exclude-dir-pattern: 按条目的 directory 排除, 如 `**/CMakeFiles/**` 排除 CMake 构建树中的命令
+    pub fn excludes_directory(&mut self, directory: &str) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let normalized = paths::normalize_lexically(Path::new(directory));
+        let normalized = normalized.to_string_lossy();
+        self.matches_any(&[directory, &normalized])
+    }
+
+    // 任一候选路径匹配某个模式时, 计入第一个匹配的模式
+    fn matches_any(&mut self, candidates: &[&str]) -> bool {
+        for pattern in &mut self.patterns {
+            if candidates
+                .iter()
+                .any(|candidate| pattern.matcher.is_match(candidate))
+            {
+                pattern.excluded += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    // 每个模式及其排除的条目数, 按命令行中的顺序
+    pub fn counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.patterns
+            .iter()
+            .map(|pattern| (pattern.pattern.as_str(), pattern.excluded))
+    }
+}
+
+// --only-under: 只保留源文件位于给定目录之下的条目
+// 比较时两边都解析掉符号链接, 指向同一目录的不同路径视为相同
+#[derive(Default)]
+pub struct RootFilter {
+    roots: Vec<PathBuf>,
+    outside: usize,
+}
+
+impl RootFilter {
+    pub fn new(roots: &[String]) -> Result<RootFilter, String> {
+        let roots = roots
+            .iter()
+            .map(|root| {
+                Path::new(root)
+                    .canonicalize()
+                    .map_err(|err| format!("无法解析 --only-under 目录 {}: {}", root, err))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(RootFilter { roots, outside: 0 })
+    }
+
+    // 源文件是否位于某个目录之下; 没有给出目录时保留所有条目
+    pub fn keeps(&mut self, directory: &str, file: &str) -> bool {
+        if self.roots.is_empty() {
+            return true;
+        }
+        let path = canonicalize_best_effort(&Path::new(directory).join(file));
+        let inside = self.roots.iter().any(|root| path.starts_with(root));
+        if !inside {
+            self.outside += 1;
+        }
+        inside
+    }
+
+    // 因不在目录之下而去掉的条目数
+    pub fn outside(&self) -> usize {
+        self.outside
+    }
+}
+
+// 默认视为系统目录的前缀
+pub const DEFAULT_SYSTEM_PREFIXES: &[&str] = &["/usr", "/opt", "/System"];
+
+// --no-system-commands: 去掉源文件位于系统目录下的条目, 如安装在 /usr 下的第三方库
+// 系统目录不一定存在, 只按路径本身比较
+#[derive(Default)]
+pub struct SystemFilter {
+    prefixes: Vec<PathBuf>,
+    dropped: usize,
+}
+
+impl SystemFilter {
+    pub fn new(prefixes: &[String]) -> SystemFilter {
+        SystemFilter {
+            prefixes: prefixes
+                .iter()
+                .map(|prefix| paths::normalize_lexically(Path::new(prefix)))
+                .collect(),
+            dropped: 0,
+        }
+    }
+
+    // 源文件是否位于系统目录之下; 没有给出前缀时不去掉任何条目
+    pub fn drops(&mut self, directory: &str, file: &str) -> bool {
+        if self.prefixes.is_empty() {
+            return false;
+        }
+        let path = canonicalize_best_effort(&Path::new(directory).join(file));
+        let system = self.prefixes.iter().any(|prefix| path.starts_with(prefix));
+        if system {
+            self.dropped += 1;
+        }
+        system
+    }
+
+    // 因位于系统目录而去掉的条目数
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+// 尽量解析符号链接: 文件还不存在时 (如生成的源文件) 解析所在目录, 再不行就只按路径本身规范化
+fn canonicalize_best_effort(path: &Path) -> PathBuf {
+    if let Ok(resolved) = path.canonicalize() {
+        return resolved;
+    }
+    let path = paths::normalize_lexically(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => path.clone(),
+        },
+        _ => path.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn root_filter_keeps_only_sources_under_the_scope() {
+        let dir = TempDir::new();
+        dir.write("proj/src/a.c", "");
+        dir.write("proj2/c.c", "");
+        dir.write("other/b.c", "");
+        let root = dir.path().to_string_lossy().to_string();
+        let mut filter = RootFilter::new(&[format!("{}/proj", root)]).unwrap();
+
+        assert!(filter.keeps(&format!("{}/proj/build", root), "../src/a.c"));
+        // 还不存在的生成文件按所在目录判断
+        assert!(filter.keeps(&format!("{}/proj/gen", root), "x.c"));
+        assert!(!filter.keeps(&root, "other/b.c"));
+        // 只是名字以 proj 开头的目录不算在内
+        assert!(!filter.keeps(&root, "proj2/c.c"));
+        assert_eq!(filter.outside(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn root_filter_resolves_symlinks() {
+        let dir = TempDir::new();
+        dir.write("proj/src/a.c", "");
+        std::os::unix::fs::symlink(dir.path().join("proj"), dir.path().join("alias")).unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+
+        let mut filter = RootFilter::new(&[format!("{}/alias", root)]).unwrap();
+        assert!(filter.keeps(&format!("{}/proj", root), "src/a.c"));
+        let mut filter = RootFilter::new(&[format!("{}/proj", root)]).unwrap();
+        assert!(filter.keeps(&format!("{}/alias/src", root), "a.c"));
+    }
+
+    #[test]
+    fn root_filter_without_scope_keeps_everything() {
+        let mut filter = RootFilter::default();
+        assert!(filter.keeps("/anywhere", "a.c"));
+        assert_eq!(filter.outside(), 0);
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn exclude_matches_relative_and_absolute_sources() {
+        let mut filter = ExcludeFilter::new(&strings(&["vendor/**", "/src/gen/*.c"])).unwrap();
+        assert!(filter.excludes("/src", "vendor/zlib/inflate.c"));
+        assert!(filter.excludes("/src", "./vendor/a.c"));
+        assert!(filter.excludes("/src/gen", "a.c"));
+        // `*` 不跨目录
+        assert!(!filter.excludes("/src", "gen/sub/a.c"));
+        // 不在 directory 之下时只按绝对路径匹配
+        assert!(!filter.excludes("/src/build", "../vendor/b.c"));
+        assert!(!filter.excludes("/src", "main.c"));
+        let counts: Vec<(&str, usize)> = filter.counts().collect();
+        assert_eq!(counts, [("vendor/**", 2), ("/src/gen/*.c", 1)]);
+    }
+
+    #[test]
+    fn exclude_dir_pattern_matches_the_directory() {
+        let mut filter = ExcludeFilter::new(&strings(&["**/CMakeFiles/**", "/b/gen"])).unwrap();
+        assert!(filter.excludes_directory("/b/CMakeFiles/CMakeTmp"));
+        assert!(!filter.excludes_directory("/b/src"));
+        // 按规范化之后的路径也匹配
+        assert!(filter.excludes_directory("/b/src/../gen"));
+        assert!(filter.excludes_directory("/b/./gen"));
+        let counts: Vec<(&str, usize)> = filter.counts().collect();
+        assert_eq!(counts, [("**/CMakeFiles/**", 1), ("/b/gen", 2)]);
+    }
+
+    #[test]
+    fn exclude_rejects_invalid_globs_and_defaults_to_nothing() {
+        assert!(ExcludeFilter::new(&strings(&["src/[a"])).is_err());
+        let mut filter = ExcludeFilter::default();
+        assert!(!filter.excludes("/src", "a.c"));
+        assert!(!filter.excludes_directory("/src"));
+    }
+
+    #[test]
+    fn system_filter_drops_sources_under_system_prefixes() {
+        let mut filter = SystemFilter::new(&strings(DEFAULT_SYSTEM_PREFIXES));
+        assert!(filter.drops("/usr/src/lib", "a.c"));
+        assert!(filter.drops("/build", "/opt/sdk/b.c"));
+        assert!(!filter.drops("/home/me/src", "a.c"));
+        // 前缀按路径的组成部分比较, 并先解析掉 `..`
+        assert!(!filter.drops("/usrlocal", "a.c"));
+        assert!(!filter.drops("/opt/../home", "a.c"));
+        assert_eq!(filter.dropped(), 2);
+
+        let mut filter = SystemFilter::default();
+        assert!(!filter.drops("/usr/src", "a.c"));
+        assert_eq!(filter.dropped(), 0);
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::parse::ParseContext;
+use crate::paths;
+use crate::shell;
+
+// 已写出的源文件条目, 构建结束后据此查找依赖文件
+struct CapturedSource {
+    directory: String,
+    command: String,
+    // 命令中出现的源文件参数 (未解析的原始路径)
+    source: String,
+}
+
+// 为头文件合成的条目: 使用包含它的源文件的命令, 把源文件换成头文件
+pub struct HeaderEntry {
+    pub directory: String,
+    pub command: String,
+    pub file: String,
+}
+
+// --include-headers: 记录写出的源文件, 构建结束后从依赖文件 (.d) 中找出头文件
+#[derive(Default)]
+pub struct HeaderCollector {
+    sources: Vec<CapturedSource>,
+}
+
+impl HeaderCollector {
+    pub fn push(&mut self, directory: &str, command: &str, source: &str) {
+        self.sources.push(CapturedSource {
+            directory: directory.to_string(),
+            command: command.to_string(),
+            source: source.to_string(),
+        });
+    }
+
+    // 每个头文件一个条目, 被多个源文件包含时取数据库中第一个源文件的命令
+    pub async fn entries(self, ctx: &ParseContext) -> Vec<HeaderEntry> {
+        // 已经作为源文件出现的文件不再重复生成
+        let mut seen: HashSet<PathBuf> = self
+            .sources
+            .iter()
+            .map(|source| absolute(&source.directory, &source.source))
+            .collect();
+        let mut entries = Vec::new();
+        for source in &self.sources {
+            let Ok(args) = shell::split(&source.command) else {
+                continue;
+            };
+            let Some(depfile) = find_depfile(&args, &source.directory) else {
+                debug!("找不到 {} 的依赖文件", source.source);
+                continue;
+            };
+            let content = match tokio::fs::read_to_string(&depfile).await {
+                Ok(content) => content,
+                Err(err) => {
+                    debug!("无法读取依赖文件 {}: {}", depfile.display(), err);
+                    continue;
+                }
+            };
+            for header in parse_depfile(&content) {
+                if ctx.is_source_arg(&header) || !seen.insert(absolute(&source.directory, &header))
+                {
+                    continue;
+                }
+                let command: Vec<&str> = args
+                    .iter()
+                    .map(|arg| {
+                        if *arg == source.source {
+                            header.as_str()
+                        } else {
+                            arg.as_str()
+                        }
+                    })
+                    .collect();
+                entries.push(HeaderEntry {
+                    directory: source.directory.clone(),
+                    command: shell::join(&command),
+                    file: header,
+                });
+            }
+        }
+        entries
+    }
+}
+
+// --read-dep-files: 编译命令生成的依赖文件中列出的头文件, 路径保持依赖文件中的写法
+// 命令不生成依赖文件或依赖文件无法读取时返回 None
+pub async fn read_dep_headers(
+    directory: &str,
+    command: &str,
+    ctx: &ParseContext,
+) -> Option<Vec<String>> {
+    let args = shell::split_command(command).ok()?;
+    let depfile = find_depfile(&args, directory)?;
+    let content = match tokio::fs::read_to_string(&depfile).await {
+        Ok(content) => content,
+        Err(err) => {
+            debug!("无法读取依赖文件 {}: {}", depfile.display(), err);
+            return None;
+        }
+    };
+    let mut seen = HashSet::new();
+    Some(
+        parse_depfile(&content)
+            .into_iter()
+            .filter(|header| !ctx.is_source_arg(header) && seen.insert(header.clone()))
+            .collect(),
+    )
+}
+
+fn absolute(directory: &str, file: &str) -> PathBuf {
+    match paths::resolve_source(directory, file) {
+        Ok(resolved) => PathBuf::from(resolved),
+        Err(_) => PathBuf::from(paths::absolute_lexical(directory, file)),
+    }
+}
+
+// 依赖文件的位置: -MF 指定的文件, 否则 -MD / -MMD 时在目标文件旁边
+// 也识别经由预处理器传递的 -Wp,-MD,<文件> 和 -Wp,-MMD,<文件>, Linux 内核的 Kbuild 就是这样生成的
+fn find_depfile(args: &[String], directory: &str) -> Option<PathBuf> {
+    let mut depfile = None;
+    let mut object = None;
+    let mut generates_deps = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-MF" => depfile = iter.next().cloned(),
+            "-o" => object = iter.next().cloned(),
+            "-MD" | "-MMD" => generates_deps = true,
+            _ if arg.starts_with("-MF") => depfile = Some(arg[3..].to_string()),
+            _ if arg.starts_with("-Wp,") => {
+                let mut options = arg.split(',').skip(1);
+                while let Some(option) = options.next() {
+                    if matches!(option, "-MD" | "-MMD" | "-MF") {
+                        if let Some(path) = options.next() {
+                            depfile = Some(path.to_string());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let directory = Path::new(directory);
+    if let Some(depfile) = depfile {
+        return Some(directory.join(depfile));
+    }
+    if !generates_deps {
+        return None;
+    }
+    // gcc 把目标文件的后缀换成 .d, 有些构建系统则直接在后面加上 .d
+    let object = directory.join(object?);
+    [
+        object.with_extension("d"),
+        PathBuf::from(format!("{}.d", object.display())),
+    ]
+    .into_iter()
+    .find(|candidate| candidate.is_file())
+}
+
+// 解析 Makefile 格式的依赖文件, 返回所有规则的依赖 (不含目标)
+// 支持反斜杠续行、`\ ` 转义的空格和 `$$`
+fn parse_depfile(content: &str) -> Vec<String> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut dependencies = Vec::new();
+    for line in content.lines() {
+        let words = split_make_words(line);
+        // 目标以 `:` 结束, 之后是依赖; -MP 生成的空规则没有依赖
+        let Some(separator) = words.iter().position(|word| word.ends_with(':')) else {
+            continue;
+        };
+        dependencies.extend(words.into_iter().skip(separator + 1));
+    }
+    dependencies
+}
+
+fn split_make_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#')) => {
+                current.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                current.push('$');
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::PathStyle;
+    use crate::testing::TempDir;
+
+    const DEPFILE: &str = "obj/foo.o: src/foo.c include/foo.h \\\n  include/my\\ dir/bar.h /usr/include/stdio.h \\\n  gen/$$x.h\ninclude/foo.h:\n";
+
+    fn context(directory: &str) -> ParseContext {
+        ParseContext::new(&[], &[], &[], directory.to_string(), PathStyle::Native)
+    }
+
+    #[test]
+    fn depfile_lists_dependencies_of_every_rule() {
+        assert_eq!(
+            parse_depfile(DEPFILE),
+            [
+                "src/foo.c",
+                "include/foo.h",
+                "include/my dir/bar.h",
+                "/usr/include/stdio.h",
+                "gen/$x.h",
+            ]
+        );
+        assert_eq!(parse_depfile("a.o: a.c \\\r\n b.h\r\n"), ["a.c", "b.h"]);
+    }
+
+    #[tokio::test]
+    async fn dep_headers_are_read_from_the_depfile_of_the_command() {
+        let dir = TempDir::new();
+        let root = dir.path().to_string_lossy().to_string();
+        let ctx = context(&root);
+        dir.write("obj/foo.d", DEPFILE);
+        dir.write("deps/foo.dep", "foo.o: foo.c foo.h\n");
+
+        let expected = [
+            "include/foo.h",
+            "include/my dir/bar.h",
+            "/usr/include/stdio.h",
+            "gen/$x.h",
+        ];
+        // -MMD 时在目标文件旁边
+        let headers = read_dep_headers(&root, "gcc -MMD -c src/foo.c -o obj/foo.o", &ctx).await;
+        assert_eq!(headers.unwrap(), expected);
+        let headers = read_dep_headers(&root, "gcc -MD -MF deps/foo.dep -c foo.c", &ctx).await;
+        assert_eq!(headers.unwrap(), ["foo.h"]);
+        let headers = read_dep_headers(&root, "gcc -Wp,-MD,deps/foo.dep -c foo.c", &ctx).await;
+        assert_eq!(headers.unwrap(), ["foo.h"]);
+
+        // 不生成依赖文件, 或依赖文件不存在
+        assert_eq!(
+            read_dep_headers(&root, "gcc -c src/foo.c -o obj/foo.o", &ctx).await,
+            None
+        );
+        assert_eq!(
+            read_dep_headers(&root, "gcc -MMD -c a.c -o obj/a.o", &ctx).await,
+            None
+        );
+    }
+}
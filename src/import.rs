@@ -0,0 +1,220 @@
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::compile_command::{CompileCommand, CompileCommandBuilder};
+use crate::shell;
+
+// 读取 compile_flags.txt: 每行一个参数, 忽略空行
+pub async fn read_flags_file(path: &Path) -> io::Result<Vec<String>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+// 用 compile_flags.txt 中的公共参数为每个源文件合成一个条目
+pub async fn from_flags(
+    flags_file: &Path,
+    sources: &[&str],
+    directory: &str,
+) -> io::Result<Vec<CompileCommand>> {
+    let flags = read_flags_file(flags_file).await?;
+    Ok(sources
+        .iter()
+        .map(|source| {
+            CompileCommandBuilder::new(compiler_for(source), source)
+                .flags(&flags)
+                .build(directory)
+        })
+        .collect())
+}
+
+// 合成命令使用的编译器: C 源文件用 cc, 其他用 c++
+fn compiler_for(source: &str) -> &'static str {
+    let is_c = Path::new(source).extension().is_some_and(|ext| ext == "c");
+    if is_c {
+        "cc"
+    } else {
+        "c++"
+    }
+}
+
+// ninja -t compdb 输出的条目, 命令可能是 command 也可能是 arguments
+#[derive(Debug, Deserialize)]
+struct NinjaEntry {
+    directory: String,
+    command: Option<String>,
+    arguments: Option<Vec<String>>,
+    file: String,
+}
+
+// 运行 ninja -C <build_dir> -t compdb cc cxx, 读取 ninja 自己生成的编译数据库
+// ninja 是要运行的程序, 也可以是兼容的 samu
+pub async fn from_ninja_compdb(
+    ninja: &str,
+    build_dir: &str,
+    cwd: &Path,
+) -> Result<Vec<CompileCommand>, String> {
+    let output = Command::new(ninja)
+        .args(["-C", build_dir, "-t", "compdb", "cc", "cxx"])
+        .current_dir(cwd)
+        .output()
+        .await
+        .map_err(|err| format!("无法运行 {}: {}", ninja, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} -t compdb 执行失败 ({}): {}",
+            ninja,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let entries: Vec<NinjaEntry> = serde_json::from_slice(&output.stdout)
+        .map_err(|err| format!("无法解析 {} -t compdb 的输出: {}", ninja, err))?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let command = match (entry.command, entry.arguments) {
+                (Some(command), _) => command,
+                (None, Some(arguments)) => shell::join(&arguments),
+                (None, None) => {
+                    return Err(format!("条目 {:?} 没有 command 或 arguments", entry.file))
+                }
+            };
+            Ok(CompileCommand {
+                directory: entry.directory,
+                command,
+                file: entry.file,
+                compiler: None,
+                headers: None,
+            })
+        })
+        .collect()
+}
+
+// 没有启用 bazel 功能时 --bazel 和 --from-bazel-aquery 的错误信息
+pub const BAZEL_DISABLED: &str =
+    "bear_rs 编译时没有启用 bazel 功能, 请用 cargo build --features bazel 重新编译";
+
+// bazel aquery --output=jsonproto 的输出中用到的部分
+#[cfg(feature = "bazel")]
+#[derive(Debug, Deserialize)]
+struct AqueryOutput {
+    #[serde(default)]
+    actions: Vec<AqueryAction>,
+}
+
+#[cfg(feature = "bazel")]
+#[derive(Debug, Deserialize)]
+struct AqueryAction {
+    mnemonic: String,
+    #[serde(default)]
+    arguments: Vec<String>,
+}
+
+// 用 bazel aquery 查询 targets 的所有 C/C++ 编译动作
+// 动作的参数都是相对于 execroot 的路径 (包括 external/ 和 bazel-out/ 下的), 因此 directory 取 execroot
+#[cfg(feature = "bazel")]
+pub async fn from_bazel_aquery(
+    bazel: &str,
+    targets: &[String],
+    cwd: &Path,
+) -> Result<Vec<CompileCommand>, String> {
+    let execution_root = run_bazel(bazel, &["info", "execution_root"], cwd).await?;
+    let execution_root = String::from_utf8_lossy(&execution_root).trim().to_string();
+
+    let query = format!("mnemonic(\"CppCompile\", deps({}))", targets.join(" + "));
+    let output = run_bazel(bazel, &["aquery", &query, "--output=jsonproto"], cwd).await?;
+    let output: AqueryOutput = serde_json::from_slice(&output)
+        .map_err(|err| format!("无法解析 {} aquery 的输出: {}", bazel, err))?;
+
+    Ok(output
+        .actions
+        .into_iter()
+        .filter(|action| action.mnemonic == "CppCompile")
+        .filter_map(|action| {
+            // Bazel 总是以 -c <源文件> 的形式给出源文件
+            let position = action.arguments.iter().position(|arg| arg == "-c")?;
+            let file = action.arguments.get(position + 1)?.clone();
+            Some(CompileCommand {
+                directory: execution_root.clone(),
+                command: shell::join(&action.arguments),
+                file,
+                compiler: None,
+                headers: None,
+            })
+        })
+        .collect())
+}
+
+#[cfg(not(feature = "bazel"))]
+pub async fn from_bazel_aquery(
+    _bazel: &str,
+    _targets: &[String],
+    _cwd: &Path,
+) -> Result<Vec<CompileCommand>, String> {
+    Err(BAZEL_DISABLED.to_string())
+}
+
+// 运行 bazel 的一个命令, 返回标准输出
+#[cfg(feature = "bazel")]
+async fn run_bazel(bazel: &str, args: &[&str], cwd: &Path) -> Result<Vec<u8>, String> {
+    let output = Command::new(bazel)
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .await
+        .map_err(|err| format!("无法运行 {}: {}", bazel, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} {} 执行失败 ({}): {}",
+            bazel,
+            args[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn from_flags_writes_one_entry_per_source_with_the_flags_first() {
+        let dir = TempDir::new();
+        let flags = dir.write("compile_flags.txt", "-Iinclude\n\n-DDEBUG=1\n-std=c++17\n");
+        let sources = ["a.c", "src/b.cpp", "c.cc"];
+        let entries = from_flags(&flags, &sources, "/project").await.unwrap();
+        assert_eq!(entries.len(), sources.len());
+        for (entry, source) in entries.iter().zip(sources) {
+            assert_eq!(entry.directory, "/project");
+            assert_eq!(entry.file, source);
+        }
+        assert_eq!(
+            entries[0].command,
+            "cc -Iinclude -DDEBUG=1 -std=c++17 -c a.c"
+        );
+        assert_eq!(
+            entries[1].command,
+            "c++ -Iinclude -DDEBUG=1 -std=c++17 -c src/b.cpp"
+        );
+    }
+
+    #[tokio::test]
+    async fn from_flags_quotes_flags_with_spaces() {
+        let dir = TempDir::new();
+        let flags = dir.write("compile_flags.txt", "-DNAME=\"a b\"\n");
+        let entries = from_flags(&flags, &["a.c"], "/project").await.unwrap();
+        assert_eq!(entries[0].command, "cc '-DNAME=\"a b\"' -c a.c");
+    }
+}
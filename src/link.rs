@@ -0,0 +1,186 @@
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::BufWriter;
+
+use crate::compile_command::FLAGS_WITH_VALUE;
+use crate::output::{JsonArrayWriter, TempPath};
+use crate::parse::{self, ParseContext};
+use crate::shell;
+
+// --link-commands 写出的文件名, 与编译数据库放在同一目录
+pub const LINK_COMMANDS_FILE_NAME: &str = "link_commands.json";
+
+// 链接命令的输入只能是这些目标文件和库
+const LINK_INPUT_EXTENSIONS: &[&str] = &["o", "obj", "a", "lo", "so", "lib", "dylib"];
+
+// 链接时值作为下一个参数单独给出的选项
+const LINK_FLAGS_WITH_VALUE: &[&str] = &[
+    "-L",
+    "-l",
+    "-T",
+    "-z",
+    "-m",
+    "-e",
+    "-u",
+    "-h",
+    "-soname",
+    "-rpath",
+    "-rpath-link",
+    "-Map",
+];
+
+// link_commands.json 中的一个条目
+#[derive(Debug, Serialize)]
+pub struct LinkCommand {
+    pub directory: String,
+    pub command: String,
+    pub output: String,
+}
+
+// 识别链接命令并写入 link_commands.json
+pub struct LinkRecorder {
+    // 直接调用的链接器和归档工具, 可以带路径和交叉编译前缀, 如 x86_64-linux-gnu-ld.gold
+    // 只在命令的开头匹配, 不把 -fuse-ld=lld 之类的参数当成命令
+    linker_regex: Regex,
+    writer: JsonArrayWriter<BufWriter<File>>,
+    temp: TempPath,
+}
+
+impl LinkRecorder {
+    pub async fn create(path: &Path, compact: bool) -> io::Result<LinkRecorder> {
+        let temp = TempPath::new(path);
+        let file = File::create(temp.temp_path()).await?;
+        Ok(LinkRecorder {
+            linker_regex: Regex::new(
+                r"(?:^|[\s;&|(])((?:[\w.+-]*/)*(?:[\w.]+-)*(?:ld(?:\.\w+)?|lld|ar))\s",
+            )
+            .unwrap(),
+            writer: JsonArrayWriter::new(BufWriter::new(file), compact),
+            temp,
+        })
+    }
+
+    // 找出一行中的链接命令 (命令本身和输出文件):
+    // 编译器驱动不带 -c 的调用, 以及直接调用的 ld / lld / ar
+    pub fn find<'a>(&self, line: &'a str, ctx: &ParseContext) -> Vec<(&'a str, String)> {
+        let drivers = parse::extract_commands(line, ctx)
+            .into_iter()
+            .filter_map(|command| Some((command, driver_output(command)?)));
+        let linkers = self
+            .linker_regex
+            .captures_iter(line)
+            .map(|caps| caps.get(1).unwrap().start())
+            .filter_map(|start| {
+                let mut command = &line[start..];
+                if let Some(noise) = ctx.noise_regex.find(command) {
+                    command = &command[..noise.start()];
+                }
+                let command = command.trim_end();
+                Some((command, linker_output(command)?))
+            });
+        drivers.chain(linkers).collect()
+    }
+
+    pub async fn write(&mut self, link_command: &LinkCommand) -> io::Result<()> {
+        self.writer.write_value(link_command).await
+    }
+
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.writer.write_end().await?;
+        self.temp.persist().await
+    }
+
+    pub async fn discard(self) {
+        drop(self.writer);
+        self.temp.discard().await;
+    }
+}
+
+fn split(command: &str) -> Vec<String> {
+    shell::split(command).unwrap_or_else(|_| command.split_whitespace().map(String::from).collect())
+}
+
+fn is_link_input(arg: &str) -> bool {
+    Path::new(arg)
+        .extension()
+        .is_some_and(|ext| LINK_INPUT_EXTENSIONS.iter().any(|known| ext == *known))
+}
+
+// 编译器驱动的链接: 有 -o, 没有 -c, 输入都是目标文件或库, 返回输出文件
+fn driver_output(command: &str) -> Option<String> {
+    let args = split(command);
+    let mut output = None;
+    let mut inputs = 0;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-c" | "-S" | "-E" => return None,
+            "-o" => output = iter.next().cloned(),
+            flag if FLAGS_WITH_VALUE.contains(&flag) || LINK_FLAGS_WITH_VALUE.contains(&flag) => {
+                iter.next();
+            }
+            _ if arg.starts_with("-o") => output = Some(arg[2..].to_string()),
+            _ if arg.starts_with('-') => {}
+            _ if is_link_input(arg) => inputs += 1,
+            _ => return None,
+        }
+    }
+    (inputs > 0).then_some(output).flatten()
+}
+
+// 直接调用链接器或 ar 时的输出文件
+fn linker_output(command: &str) -> Option<String> {
+    let args = split(command);
+    let program = Path::new(args.first()?)
+        .file_name()?
+        .to_string_lossy()
+        .to_string();
+    if program == "ar" || program.ends_with("-ar") {
+        // ar <操作> <归档文件> <目标文件>...
+        let archive = args.get(2)?;
+        let members = &args[3.min(args.len())..];
+        let valid = archive.ends_with(".a")
+            && !members.is_empty()
+            && members.iter().all(|member| is_link_input(member));
+        return valid.then(|| archive.clone());
+    }
+    driver_output(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driver_link_needs_output_and_only_objects_or_libraries() {
+        assert_eq!(
+            driver_output("gcc main.o util.o -L lib -lm -o app").as_deref(),
+            Some("app")
+        );
+        assert_eq!(
+            driver_output("g++ -oapp main.o libx.a").as_deref(),
+            Some("app")
+        );
+        assert_eq!(driver_output("gcc -c a.c -o a.o"), None);
+        // 输入中有源文件时不算, 如直接从源文件编译并链接
+        assert_eq!(driver_output("gcc main.c -o app"), None);
+        assert_eq!(driver_output("gcc main.o"), None);
+    }
+
+    #[test]
+    fn linker_and_archiver_outputs() {
+        assert_eq!(
+            linker_output("ld -o app crt1.o main.o").as_deref(),
+            Some("app")
+        );
+        assert_eq!(
+            linker_output("arm-none-eabi-ar rcs libx.a a.o b.o").as_deref(),
+            Some("libx.a")
+        );
+        assert_eq!(linker_output("ar t libx.a"), None);
+    }
+}
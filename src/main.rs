@@ -1,22 +1,52 @@
-use std::io;
+mod compile_command;
+mod config;
+mod database;
+mod filter;
+mod headers;
+mod import;
+mod link;
+mod output;
+mod parse;
+mod paths;
+mod progress;
+mod recorder;
+mod shell;
+mod sqlite;
+#[cfg(test)]
+mod testing;
+mod xcode;
+
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use clap::crate_authors;
-use regex::Regex;
-use tokio::fs::File;
+use config::Config;
+use filter::{ExcludeFilter, RootFilter, SystemFilter};
+use headers::HeaderCollector;
+use link::LinkRecorder;
+use output::{Output, OutputTask, TempPath};
+use parse::{ContinuationJoiner, FragmentJoiner, Language, ParseContext};
+use paths::{CompilerResolver, PathStyle, PrefixMap, RelativeTo};
+use progress::{Progress, ProgressMode};
+use recorder::{NonMatchingWriter, OnLimit, Recorder, Stats, Validation};
+use xcode::XcodeParser;
 use tokio::io::AsyncBufReadExt;
-use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
 use tokio::process::Command;
-
-#[derive(Debug, serde::Serialize)]
-struct CompileCommand {
-    directory: String,
-    command: String,
-    file: String,
-}
+use tracing::{debug, info, warn};
 
 #[tokio::main(worker_threads = 6)]
 async fn main() -> io::Result<()> {
+    // 整个流程放到工作线程上运行, 与写出任务由同一组线程调度, 每写出一条不必唤醒另一个线程
+    // profiling 的根 span 不能跨线程传递, 此时仍在主线程上运行
+    #[cfg(feature = "profiling")]
+    return run().await;
+    #[cfg(not(feature = "profiling"))]
+    tokio::spawn(run()).await.map_err(io::Error::other)?
+}
+
+async fn run() -> io::Result<()> {
     // 从命令行参数获取命令和参数
     let matches = clap::Command::new("bear_rs")
         .version("1.0")
@@ -26,153 +56,1878 @@ async fn main() -> io::Result<()> {
             "{bin} {version} present by {author-with-newline}\
             {about}\n\n\
             {usage-heading} {usage}\n\n\
-            {all-args}\n",
+            {all-args}{after-help}\n",
         )
-        .override_usage("Usage: bear_rs [OPTIONS] -- [COMMAND] [ARGS]...\n\nUse `--` to separate bear_rs options from the command to be run.")
+        .after_help("Exit status:\n  The exit status of the build command; the database is written either way.\n  126  the build command could not be started; no database is written\n  127  the build command was not found; no database is written\n  130  interrupted with Ctrl-C; the entries captured so far are still written as a valid database")
+        .override_usage("Usage: bear_rs [OPTIONS] -- [COMMAND] [ARGS]...\n       bear_rs query --file SOURCE [--database PATH]\n\nUse `--` to separate bear_rs options from the command to be run.")
         .arg(
             clap::Arg::new("output_dir")
             .short('o')
             .long("output-dir")
             .value_name("DIR")
-            .help("Sets the output directory")
+            .help("Sets the output directory, or `-` to write the database to stdout")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("output_file")
+            .long("output-file")
+            .value_name("PATH")
+            .help("Write the database to PATH (relative to --output-dir if given), or `-` for stdout")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("cwd")
+            .long("cwd")
+            .value_name("DIR")
+            .help("Run the command in DIR and use it as the `directory` of every entry")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("working_directory")
+            .long("working-directory")
+            .value_name("DIR")
+            .help("Use DIR as the `directory` of every entry, without changing where the command runs (unlike --cwd)")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("config")
+            .long("config")
+            .value_name("PATH")
+            .help("Read defaults from PATH instead of .bear_rs.toml in the output directory or current directory")
+            .conflicts_with("no_config")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("no_config")
+            .long("no-config")
+            .help("Do not read any .bear_rs.toml config file")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format: compile_commands.json, JSON Lines (compile_commands.jsonl), a compile_flags.txt in each build directory, an SQLite database (compile_commands.sqlite), or a CSV table with file, directory and command columns (compile_commands.csv)")
+            .value_parser(OUTPUT_FORMATS.to_vec())
+            .default_value("json"),
+        )
+        .arg(
+            clap::Arg::new("compact")
+            .long("compact")
+            .help("Write minified JSON instead of pretty-printed JSON")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("no_csv_header")
+            .long("no-csv-header")
+            .help("With --format csv, do not write the `file,directory,command` header row")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("compiler")
+            .long("compiler")
+            .value_name("NAME")
+            .help("Also recognize NAME as a compiler, e.g. `emcc` (repeatable)")
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("only_compiler")
+            .long("only-compiler")
+            .value_name("NAME")
+            .help("Only record commands run by the compiler named NAME, without its directory, e.g. `clang++` (repeatable)")
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("language")
+            .long("language")
+            .value_name("LANG")
+            .help("Only record C or only C++ commands, judged by `-x` or else the source extension; giving both records everything (repeatable)")
+            .value_parser(["c", "c++"])
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("keep_preprocess")
+            .long("keep-preprocess")
+            .help("Also record probe invocations that produce no object file, e.g. `-E`, `-fsyntax-only`, `-M` without `-c`, or `--version`")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("require_output")
+            .long("require-output")
+            .value_name("BOOL")
+            .help("Only record commands with `-o`; set to false for builds that let the compiler name the object file")
+            .value_parser(clap::value_parser!(bool))
+            .default_value("true"),
+        )
+        .arg(
+            clap::Arg::new("allow_flags")
+            .long("allow-flags")
+            .value_name("FLAGS")
+            .help("Also accept these flags in place of `-c`, comma-separated, e.g. `-S,-E` (repeatable)")
+            .value_delimiter(',')
+            .allow_hyphen_values(true)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("include_pch")
+            .long("include-pch")
+            .help("Also record commands that generate precompiled headers (`-x c++-header`, `.gch`/`.pch` outputs)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("source_ext")
+            .long("source-ext")
+            .value_name("EXT")
+            .help("Also treat files with extension EXT as sources, e.g. `ixx` or `cu` (repeatable)")
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("compiler_regex")
+            .long("compiler-regex")
+            .value_name("PATTERN")
+            .help("Replace the built-in compiler names with a regex, e.g. `icx|icpx` (repeatable, combined with `|`)")
+            .value_parser(parse::parse_regex)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("exclude")
+            .long("exclude")
+            .value_name("GLOB")
+            .help("Leave out entries whose source matches GLOB, as a path relative to `directory` or as an absolute path, e.g. `vendor/**` (repeatable)")
+            .value_parser(filter::parse_glob)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("exclude_dir_pattern")
+            .long("exclude-dir-pattern")
+            .value_name("GLOB")
+            .help("Leave out entries whose `directory` matches GLOB, e.g. `**/CMakeFiles/**` (repeatable)")
+            .value_parser(filter::parse_glob)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("only_under")
+            .long("only-under")
+            .visible_alias("scope")
+            .value_name("PATH")
+            .help("Keep only entries whose source lies under PATH, comparing paths with symlinks resolved (repeatable)")
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("no_system_commands")
+            .long("no-system-commands")
+            .help("Leave out entries whose source lies under a system prefix (/usr, /opt and /System unless --system-prefix is given)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("system_prefix")
+            .long("system-prefix")
+            .value_name("PATH")
+            .help("Treat PATH as a system prefix for --no-system-commands, replacing the default list (repeatable)")
+            .requires("no_system_commands")
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("path_style")
+            .long("path-style")
+            .value_name("STYLE")
+            .help("Path separators in `directory` and `file`: keep them as-is, or convert backslashes to forward slashes")
+            .value_parser(["native", "unix"])
+            .default_value("native"),
+        )
+        .arg(
+            clap::Arg::new("absolute_paths")
+            .long("absolute-paths")
+            .help("Make `file` absolute even when the source does not exist yet, by normalizing the path lexically")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("relative_to")
+            .long("relative-to")
+            .value_name("DIR")
+            .help("Write `file` (and the `output` of link commands) relative to the entry's `directory` when the path is under DIR, keeping other paths absolute")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("relative_paths")
+            .long("relative-paths")
+            .help("Write `directory` relative to the directory of the database and `file` relative to `directory`, keeping absolute paths outside the database directory")
+            .conflicts_with("relative_to")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("strip_flag_pattern")
+            .long("strip-flag-pattern")
+            .value_name("REGEX")
+            .help("Remove the arguments that REGEX matches in full from every recorded command, e.g. `-fsanitize=.*` or `--coverage` (repeatable)")
+            .value_parser(parse::parse_regex)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("prepend_flag")
+            .long("prepend-flag")
+            .value_name("FLAG")
+            .help("Insert FLAG right after the compiler in every recorded command, e.g. `-ferror-limit=0` (repeatable)")
+            .allow_hyphen_values(true)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("append_flag")
+            .long("append-flag")
+            .value_name("FLAG")
+            .help("Add FLAG at the end of every recorded command, e.g. `-Ibuild/generated` (repeatable)")
+            .allow_hyphen_values(true)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("canonicalize_includes")
+            .long("canonicalize-includes")
+            .help("Make -I, -isystem, -iquote and -idirafter directories absolute relative to `directory` and drop repeated ones")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("map_prefix")
+            .long("map-prefix")
+            .value_name("OLD=NEW")
+            .help("Rewrite paths starting with OLD to start with NEW in `directory`, `file` and `command`, e.g. `/workspace=/home/me/src` (repeatable)")
+            .value_parser(paths::parse_prefix_mapping)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("resolve_compiler")
+            .long("resolve-compiler")
+            .help("Replace the compiler in each command with its absolute path, found via PATH with symlinks followed")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("env_passthrough")
+            .long("env-passthrough")
+            .value_name("VAR")
+            .help("Prepend `VAR=value` from bear_rs's own environment to every command, e.g. `CPATH` or `SDKROOT` (repeatable)")
+            .value_parser(shell::parse_env_name)
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("emit_compiler")
+            .long("emit-compiler")
+            .help("Add a `compiler` field with the compiler's file name to every entry (not part of the compile_commands.json format)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("include_headers")
+            .long("include-headers")
+            .help("After the build, add an entry for every header listed in the dependency files (-MF, -MD/-MMD or -Wp,-MD,FILE) of captured commands, using the command of the first source that includes it. This reads one file per captured command once the build is done")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("read_dep_files")
+            .long("read-dep-files")
+            .visible_alias("read-deps")
+            .help("After the build, read the dependency file (-MF, -MD/-MMD or -Wp,-MD,FILE) of every captured command and list its headers in a non-standard `headers` field (JSON and JSONL only); entries are written at the end of the build")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("link_commands")
+            .long("link-commands")
+            .help("Also write the link steps (directory, command, output) to link_commands.json next to the database")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("link_db")
+            .long("link-db")
+            .value_name("FILE")
+            .help("Like --link-commands, but write the link steps to FILE")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("validate")
+            .long("validate")
+            .help("Check every entry against the compile_commands.json format and warn about invalid ones")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("strict")
+            .long("strict")
+            .help("With --validate, abort on the first invalid entry")
+            .requires("validate")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("max_entries")
+            .long("max-entries")
+            .value_name("N")
+            .help("Stop the build and close the database once N entries have been written")
+            .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            clap::Arg::new("on_limit")
+            .long("on-limit")
+            .value_name("POLICY")
+            .help("What to do when the build has more than --max-entries entries: `truncate` keeps the first N, `error` writes nothing and fails")
+            .requires("max_entries")
+            .value_parser(["truncate", "error"])
+            .default_value("truncate"),
+        )
+        .arg(
+            clap::Arg::new("skip_entries")
+            .long("skip-entries")
+            .value_name("N")
+            .help("Discard the first N entries that would otherwise be written")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("0"),
+        )
+        .arg(
+            clap::Arg::new("dry_run")
+            .long("dry-run")
+            .help("Run the build and match commands as usual, but only list the matched source files on stderr instead of writing the database")
+            .conflicts_with_all(["link_commands", "link_db"])
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("print_non_matching")
+            .long("print-non-matching")
+            .value_name("PATH")
+            .help("Write every build output line that was not recognized as a compile command to PATH, one per line")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("non_matching_reasons")
+            .long("non-matching-reasons")
+            .help("With --print-non-matching, prefix each line with the checks it failed, e.g. `[不包含源文件扩展名]`")
+            .requires("print_non_matching")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("color")
+            .long("color")
+            .value_name("WHEN")
+            .help("Color the log messages on stderr: auto (a terminal and NO_COLOR unset), always or never")
+            .value_parser(["auto", "always", "never"])
+            .default_value("auto"),
+        )
+        .arg(
+            clap::Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .help("Do not echo the build output or report progress (unless --progress is given), and only log warnings and errors")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("progress")
+            .long("progress")
+            .help("Show a self-updating line with the captured compile commands, scanned lines and elapsed time on stderr while the build runs, also with --quiet (only when stderr is a terminal and the build output is not echoed to a terminal, e.g. with --quiet or stdout redirected)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("progress_interval")
+            .long("progress-interval")
+            .value_name("SECONDS")
+            .help("Print the number of captured compile commands to stderr every SECONDS while the build runs, 0 to disable (only when stderr is a terminal)")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("5"),
+        )
+        .arg(
+            clap::Arg::new("stats")
+            .long("stats")
+            .help("Print a summary to stderr at the end: lines scanned, entries written, entries filtered out, and elapsed time")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("stats_json")
+            .long("stats-json")
+            .help("Like --stats, but print the summary as a JSON object")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("summary_json")
+            .long("summary-json")
+            .value_name("PATH")
+            .help("Also write the end-of-run summary (counts, skip reasons, elapsed time and output path) to PATH as JSON, e.g. for CI dashboards")
             .num_args(1),
         )
+        .arg(
+            clap::Arg::new("assume_jobserver")
+            .long("assume-jobserver")
+            .help("Reconstruct compile commands split across lines by parallel builds (auto-enabled for `make -jN`)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("from_flags")
+            .long("from-flags")
+            .value_name("FILE")
+            .help("Do not run a build; write one entry per source given in place of COMMAND, using the flags in FILE (compile_flags.txt format)")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("from_ninja_compdb")
+            .long("from-ninja-compdb")
+            .value_name("BUILD_DIR")
+            .help("Do not run a build; read the database from `ninja -C BUILD_DIR -t compdb cc cxx` instead")
+            .conflicts_with_all(["from_flags", "command"])
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("from_bazel_aquery")
+            .long("from-bazel-aquery")
+            .value_name("TARGET")
+            .help("Do not run a build; read the C/C++ compile actions of TARGET from `bazel aquery` instead (repeatable; needs the `bazel` feature)")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb", "command"])
+            .action(clap::ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("query_sqlite")
+            .long("query-sqlite")
+            .value_name("DB")
+            .help("Do not run a build; print the entry for --file from an SQLite database written with `--format sqlite`")
+            .requires("file")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb", "command"])
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("file")
+            .long("file")
+            .value_name("SOURCE")
+            .help("The source file to look up with --query-sqlite")
+            .requires("query_sqlite")
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("commands_file")
+            .long("commands-file")
+            .value_name("FILE")
+            .help("Run each line of FILE as a build command through the shell, one after another, and write the entries of all of them into one database (empty lines and `#` comments are ignored)")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb", "from_bazel_aquery", "command", "shell"])
+            .num_args(1),
+        )
+        .arg(
+            clap::Arg::new("shell")
+            .short('s')
+            .long("shell")
+            .help("Run the command through `sh -c` (`cmd /C` on Windows), e.g. `bear_rs -s -- \"make -j8 && make test\"`")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb"])
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("xcodebuild")
+            .long("xcodebuild")
+            .help("Expect xcodebuild output: record each CompileC block's clang command in the directory of its `cd` line (blocks are also detected automatically)")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("ninja_compdb")
+            .long("ninja-compdb")
+            .help("When COMMAND is ninja (or samu), also record the entries of `ninja -C DIR -t compdb` after the build, with DIR taken from its -C argument")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb", "commands_file", "shell"])
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("bazel")
+            .long("bazel")
+            .help("When COMMAND is `bazel build TARGETS...`, also record the C/C++ compile actions of TARGETS from `bazel aquery` after the build (needs the `bazel` feature)")
+            .conflicts_with_all(["from_flags", "from_ninja_compdb", "from_bazel_aquery", "commands_file", "shell"])
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("auto_verbose")
+            .long("auto-verbose")
+            .help("Add `VERBOSE=1 V=1` to make, `--verbose` to `cmake --build` and `-v` to ninja so they print compiler commands (default)")
+            .overrides_with("no_auto_verbose")
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("no_auto_verbose")
+            .long("no-auto-verbose")
+            .help("Run make, `cmake --build` and ninja exactly as given")
+            .overrides_with("auto_verbose")
+            .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             clap::Arg::new("command")
-            .help("The command to run")
-            .required(true)
+            .help("The command to run, or the source files with --from-flags")
+            .required_unless_present_any(["from_ninja_compdb", "from_bazel_aquery", "commands_file", "query_sqlite"])
             .trailing_var_arg(true)
             .num_args(1..)
             .allow_hyphen_values(true),
         )
+        .subcommand(
+            clap::Command::new("query")
+            .about("Print the entry recorded for a source file in an existing database")
+            .arg(
+                clap::Arg::new("file")
+                .long("file")
+                .value_name("SOURCE")
+                .help("The source file to look up, as recorded or relative to the current directory")
+                .required(true)
+                .num_args(1),
+            )
+            .arg(
+                clap::Arg::new("database")
+                .short('d')
+                .long("database")
+                .value_name("PATH")
+                .help("The compile_commands.json (or JSON Lines) file to read")
+                .default_value("compile_commands.json")
+                .num_args(1),
+            )
+            .arg(
+                clap::Arg::new("arguments")
+                .long("arguments")
+                .help("Print only the command, split into a JSON array of arguments")
+                .action(clap::ArgAction::SetTrue),
+            ),
+        )
+        .subcommand(
+            clap::Command::new("check")
+            .about("Check an existing database and list the problems found, exiting with 1 if there are any errors")
+            .arg(
+                clap::Arg::new("database")
+                .value_name("PATH")
+                .help("The compile_commands.json (or JSON Lines) file to check")
+                .default_value("compile_commands.json"),
+            )
+            .arg(
+                clap::Arg::new("strict")
+                .long("strict")
+                .help("Also require every directory to exist and every file to resolve to an existing path")
+                .action(clap::ArgAction::SetTrue),
+            ),
+        )
+        .subcommand(
+            clap::Command::new("diff")
+            .about("Compare two databases by directory and file, exiting with 0 if they are the same, 1 if they differ and 2 on errors")
+            .arg(
+                clap::Arg::new("old")
+                .value_name("OLD")
+                .help("The database to compare from (JSON or JSON Lines)")
+                .required(true),
+            )
+            .arg(
+                clap::Arg::new("new")
+                .value_name("NEW")
+                .help("The database to compare to (JSON or JSON Lines)")
+                .required(true),
+            )
+            .arg(
+                clap::Arg::new("flags")
+                .long("flags")
+                .help("Also list the arguments removed and added for every changed entry")
+                .action(clap::ArgAction::SetTrue),
+            ),
+        )
+        .subcommand(
+            clap::Command::new("merge")
+            .about("Combine several databases into one, keeping one entry per directory and file")
+            .arg(
+                clap::Arg::new("inputs")
+                .value_name("DATABASE")
+                .help("The databases to combine, JSON or JSON Lines; for a file that appears in several of them the last one wins")
+                .required(true)
+                .num_args(1..),
+            )
+            .arg(
+                clap::Arg::new("output_dir")
+                .short('o')
+                .long("output-dir")
+                .value_name("DIR")
+                .help("The directory to write the combined compile_commands.json to")
+                .default_value("."),
+            )
+            .arg(
+                clap::Arg::new("keep_first")
+                .long("keep-first")
+                .help("For a file that appears in several databases, keep the entry of the first one instead of the last")
+                .action(clap::ArgAction::SetTrue),
+            )
+            .arg(
+                clap::Arg::new("compact")
+                .long("compact")
+                .help("Write the database without indentation")
+                .action(clap::ArgAction::SetTrue),
+            )
+            .arg(
+                clap::Arg::new("strict")
+                .long("strict")
+                .help("Fail when an input does not exist instead of skipping it with a warning")
+                .action(clap::ArgAction::SetTrue),
+            ),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .get_matches();
+    let started = Instant::now();
+
+    let quiet = matches.get_flag("quiet");
+    init_logging(quiet, use_color(matches.get_one::<String>("color").unwrap()));
+    // 整个运行过程的根 span, 其余 span 都挂在它下面
+    #[cfg(feature = "profiling")]
+    let _run = tracing::info_span!("run").entered();
+
+    match matches.subcommand() {
+        Some(("query", query)) => {
+            query_database(query);
+            return Ok(());
+        }
+        Some(("check", check)) => check_database(check),
+        Some(("diff", diff)) => diff_databases(diff),
+        Some(("merge", merge)) => merge_databases(merge).await,
+        _ => {}
+    }
+
+    // 查询已有的 SQLite 数据库, 不运行构建
+    if let Some(database) = matches.get_one::<String>("query_sqlite") {
+        let file = matches.get_one::<String>("file").unwrap();
+        let entry = sqlite::query(Path::new(database), file)
+            .unwrap_or_else(|err| exit_with_error(&format!("无法查询数据库 {}: {}", database, err)));
+        match entry {
+            Some(entry) => println!("{}", serde_json::to_string_pretty(&entry).unwrap()),
+            None => exit_with_error(&format!("数据库 {} 中没有 {} 的条目", database, file)),
+        }
+        return Ok(());
+    }
 
     let output_dir = matches
         .get_one::<String>("output_dir")
         .map(|s| s.as_str())
         .unwrap_or(".");
-    let output_path = format!("{}/compile_commands.json", output_dir);
+
+    // 读取配置文件, 命令行参数优先于配置文件
+    let config = Config::load(
+        matches.get_one::<String>("config").map(|s| s.as_str()),
+        matches.get_flag("no_config"),
+        output_dir,
+    )
+    .await
+    .unwrap_or_else(|err| exit_with_error(&err));
+
+    let format = config::merged_value(&matches, "format", &config.format);
+    let format = format.as_str();
+    if !OUTPUT_FORMATS.contains(&format) {
+        exit_with_error(&format!(
+            "不支持的输出格式 {:?}, 可选: {}",
+            format,
+            OUTPUT_FORMATS.join(", ")
+        ));
+    }
+    let output_name = match format {
+        "jsonl" => "compile_commands.jsonl",
+        "csv" => "compile_commands.csv",
+        "sqlite" => "compile_commands.sqlite",
+        _ => "compile_commands.json",
+    };
+    let output_file = matches.get_one::<String>("output_file").map(|s| s.as_str());
+    let output_path = Path::new(output_dir).join(output_file.unwrap_or(output_name));
+    // -o - 或 --output-file - 时数据库写到标准输出, 其他输出都走标准错误
+    let to_stdout = output_dir == "-" || output_file == Some("-");
+    if to_stdout && format == "compile-flags" {
+        exit_with_error("compile-flags 格式需要按目录写文件, 不能输出到标准输出");
+    }
+    if to_stdout && format == "sqlite" {
+        exit_with_error("sqlite 格式需要写入数据库文件, 不能输出到标准输出");
+    }
+    let link_db = matches.get_one::<String>("link_db");
+    let link_commands = matches.get_flag("link_commands") || link_db.is_some();
+    if to_stdout && link_commands && link_db.is_none() {
+        exit_with_error("--link-commands 需要与数据库写在同一目录, 不能输出到标准输出");
+    }
 
     // 获取外部命令和参数
     let command_and_args: Vec<&str> = matches
         .get_many::<String>("command")
-        .unwrap()
+        .unwrap_or_default()
         .map(|s| s.as_str())
         .collect::<Vec<&str>>();
 
-    println!("命令行参数: {:?}", command_and_args);
-    let command = command_and_args[0];
-    let args: Vec<&str> = command_and_args[1..].to_vec();
+    debug!("命令行参数: {:?}", command_and_args);
 
-    // 创建输出文件
-    let mut file = File::create(output_path).await?;
-    let _ = file.write_all(b"[\n").await?;
+    // 构建的工作目录, 相对路径按 bear_rs 自己的当前目录解析
+    let build_dir = match matches.get_one::<String>("cwd") {
+        Some(cwd) => Path::new(cwd).canonicalize().unwrap_or_else(|err| {
+            exit_with_error(&format!("无法进入工作目录 {}: {}", cwd, err))
+        }),
+        None => std::env::current_dir()?,
+    };
+    if !build_dir.is_dir() {
+        exit_with_error(&format!("工作目录 {} 不是目录", build_dir.display()));
+    }
+    // 编译器实际运行的目录与构建命令不同时 (如 cmake 的外部构建目录), 由用户指定条目的 directory
+    let working_directory = matches.get_one::<String>("working_directory").map(|dir| {
+        let dir = Path::new(dir).canonicalize().unwrap_or_else(|err| {
+            exit_with_error(&format!("无法解析 --working-directory 目录 {}: {}", dir, err))
+        });
+        if !dir.is_dir() {
+            exit_with_error(&format!("--working-directory {} 不是目录", dir.display()));
+        }
+        dir.to_string_lossy().to_string()
+    });
 
-    // 运行指定的命令并获取输出
-    let process = Command::new(command)
-        .args(&args) // 将命令行参数传递给命令
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()?;
+    // make -jN 时各任务的输出会交错, 自动开启命令行重组
+    let assume_jobserver = config::merged_flag(&matches, "assume_jobserver", config.assume_jobserver)
+        || is_parallel_make(&command_and_args);
 
-    let stdout = process.stdout.unwrap();
-    let reader = BufReader::new(stdout);
-    let error_reader = BufReader::new(process.stderr.unwrap());
+    // --shell: 整条命令交给 shell 执行, 支持 && 和管道
+    let mut build_command: Vec<String> = if matches.get_flag("shell") {
+        shell_command(&command_and_args)
+    } else {
+        command_and_args.iter().map(|arg| arg.to_string()).collect()
+    };
+    // --ninja-compdb: 构建结束后从 ninja 自己的编译数据库补充条目, 此时不再需要 -v
+    let ninja_compdb = matches.get_flag("ninja_compdb").then(|| {
+        ninja_build_dir(&build_command)
+            .unwrap_or_else(|| exit_with_error("--ninja-compdb 要求构建命令是 ninja 或 samu"))
+    });
+    // --bazel: Bazel 不打印可解析的编译命令, 构建结束后用 aquery 查询
+    let bazel = matches.get_flag("bazel").then(|| {
+        if !cfg!(feature = "bazel") {
+            exit_with_error(import::BAZEL_DISABLED);
+        }
+        bazel_targets(&build_command)
+            .unwrap_or_else(|| exit_with_error("--bazel 要求构建命令是 bazel build 并给出目标"))
+    });
+    // make、cmake --build 和 ninja 默认不打印编译命令, 自动打开详细输出
+    if !matches.get_flag("shell") && !matches.get_flag("no_auto_verbose") && ninja_compdb.is_none() {
+        let injected = inject_verbosity(&mut build_command);
+        if !injected.is_empty() {
+            info!(
+                "自动添加 {} 以输出编译命令 (--no-auto-verbose 可关闭)",
+                injected.join(" ")
+            );
+        }
+    }
+
+    // 不运行构建时, 在创建输出前读入条目, 读取失败时不会覆盖已有的数据库
+    let directory = build_dir.to_string_lossy().to_string();
+    let imported = if let Some(flags_file) = matches.get_one::<String>("from_flags") {
+        let entries = import::from_flags(Path::new(flags_file), &command_and_args, &directory)
+            .await
+            .unwrap_or_else(|err| exit_with_error(&format!("无法读取 {}: {}", flags_file, err)));
+        Some(entries)
+    } else if let Some(ninja_dir) = matches.get_one::<String>("from_ninja_compdb") {
+        let entries = import::from_ninja_compdb("ninja", ninja_dir, &build_dir)
+            .await
+            .unwrap_or_else(|err| exit_with_error(&err));
+        Some(entries)
+    } else if let Some(targets) = matches.get_many::<String>("from_bazel_aquery") {
+        let targets: Vec<String> = targets.cloned().collect();
+        let entries = import::from_bazel_aquery("bazel", &targets, &build_dir)
+            .await
+            .unwrap_or_else(|err| exit_with_error(&err));
+        Some(entries)
+    } else {
+        None
+    };
+
+    // 创建输出, --dry-run 时不创建任何文件
+    let dry_run = matches.get_flag("dry_run");
+    // 这次运行新建的输出目录, 构建命令无法启动时删除
+    let mut created_dirs = Vec::new();
+    if !to_stdout && !dry_run && format != "compile-flags" {
+        created_dirs.extend(create_output_parent(&output_path).await);
+        preflight_output(&output_path).await;
+    }
+
+    // 摘要中显示的输出位置, --dry-run 时没有
+    let output_label = if dry_run {
+        None
+    } else if to_stdout {
+        Some("标准输出".to_string())
+    } else if format == "compile-flags" {
+        Some("各目录的 compile_flags.txt".to_string())
+    } else {
+        Some(output_path.display().to_string())
+    };
+
+    let compact = config::merged_flag(&matches, "compact", config.compact);
+    let csv_header = !matches.get_flag("no_csv_header");
+    let output = open_output(format, &output_path, to_stdout, dry_run, compact, csv_header)
+        .await
+        .unwrap_or_else(|err| {
+            exit_with_error(&format!("无法创建输出文件 {}: {}", output_path.display(), err))
+        });
+
+    let links = if link_commands {
+        let link_path = match link_db {
+            Some(link_db) => {
+                let link_path = PathBuf::from(link_db);
+                created_dirs.extend(create_output_parent(&link_path).await);
+                link_path
+            }
+            None => output_path.with_file_name(link::LINK_COMMANDS_FILE_NAME),
+        };
+        let links = LinkRecorder::create(&link_path, compact)
+            .await
+            .unwrap_or_else(|err| {
+                exit_with_error(&format!(
+                    "无法创建输出文件 {}: {}",
+                    link_path.display(),
+                    err
+                ))
+            });
+        Some(links)
+    } else {
+        None
+    };
+
+    let extra_compilers = config::merged_list(&matches, "compiler", &config.compilers);
+    let compiler_patterns = config::merged_list(&matches, "compiler_regex", &config.compiler_regex);
+    // 配置文件中的正则没有经过命令行检查
+    for pattern in &compiler_patterns {
+        if let Err(err) = parse::parse_regex(pattern) {
+            exit_with_error(&err);
+        }
+    }
+    // 配置文件中的 glob 同样在这里检查
+    let exclude = ExcludeFilter::new(&config::merged_list(&matches, "exclude", &config.exclude))
+        .unwrap_or_else(|err| exit_with_error(&err));
+    let exclude_dirs = ExcludeFilter::new(&config::merged_list(
+        &matches,
+        "exclude_dir_pattern",
+        &config.exclude_dir_pattern,
+    ))
+    .unwrap_or_else(|err| exit_with_error(&err));
+    let only_under = RootFilter::new(
+        &matches
+            .get_many::<String>("only_under")
+            .unwrap_or_default()
+            .cloned()
+            .collect::<Vec<_>>(),
+    )
+    .unwrap_or_else(|err| exit_with_error(&err));
+    let system = if matches.get_flag("no_system_commands") {
+        let prefixes: Vec<String> = match matches.get_many::<String>("system_prefix") {
+            Some(prefixes) => prefixes.cloned().collect(),
+            None => filter::DEFAULT_SYSTEM_PREFIXES
+                .iter()
+                .map(|prefix| prefix.to_string())
+                .collect(),
+        };
+        SystemFilter::new(&prefixes)
+    } else {
+        SystemFilter::default()
+    };
+    let path_style = match matches.get_one::<String>("path_style").unwrap().as_str() {
+        "unix" => PathStyle::Unix,
+        _ => PathStyle::Native,
+    };
+    let relative_to = if let Some(base) = matches.get_one::<String>("relative_to") {
+        let relative_to = RelativeTo::new(Path::new(base)).unwrap_or_else(|err| {
+            exit_with_error(&format!("无法解析 --relative-to 目录 {}: {}", base, err))
+        });
+        Some(relative_to)
+    } else if matches.get_flag("relative_paths") {
+        // 输出到标准输出时相对于当前目录
+        let base = match output_path.parent() {
+            Some(parent) if !to_stdout && !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let relative_to = RelativeTo::inside(base).unwrap_or_else(|err| {
+            exit_with_error(&format!("无法解析输出目录 {}: {}", base.display(), err))
+        });
+        Some(relative_to)
+    } else {
+        None
+    };
+    let source_extensions = config::merged_list(&matches, "source_ext", &config.extensions);
+    let ctx = ParseContext {
+        absolute_paths: config::merged_flag(&matches, "absolute_paths", config.absolute_paths),
+        include_pch: matches.get_flag("include_pch"),
+        compile_flags: std::iter::once("-c".to_string())
+            .chain(matches.get_many::<String>("allow_flags").unwrap_or_default().cloned())
+            .collect(),
+        require_output: *matches.get_one::<bool>("require_output").unwrap(),
+        relative_to,
+        prefix_map: PrefixMap::new(
+            matches
+                .get_many::<(String, String)>("map_prefix")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+        ),
+        ..ParseContext::new(
+            &compiler_patterns,
+            &extra_compilers,
+            &source_extensions,
+            directory,
+            path_style,
+        )
+    };
+    let validation = match (matches.get_flag("validate"), matches.get_flag("strict")) {
+        (_, true) => Validation::Strict,
+        (true, false) => Validation::Warn,
+        (false, false) => Validation::Off,
+    };
+    let interval = *matches.get_one::<u64>("progress_interval").unwrap();
+    // 自动刷新的进度行不能和回显的构建输出混在同一个终端上: 构建输出回显到标准输出,
+    // 数据库写到标准输出时则回显到标准错误, 所以只在 --quiet 或回显的输出不在终端上时使用
+    let echo_collides = to_stdout || io::stdout().is_terminal();
+    let progress_mode = if matches.get_flag("progress") && (quiet || !echo_collides) {
+        ProgressMode::Live
+    } else if quiet {
+        ProgressMode::Off
+    } else if interval > 0 {
+        ProgressMode::Periodic(Duration::from_secs(interval))
+    } else {
+        ProgressMode::Off
+    };
+    let non_matching = match matches.get_one::<String>("print_non_matching") {
+        Some(path) => Some(
+            NonMatchingWriter::create(Path::new(path), matches.get_flag("non_matching_reasons"))
+                .await
+                .unwrap_or_else(|err| exit_with_error(&format!("无法创建 {}: {}", path, err))),
+        ),
+        None => None,
+    };
+    let mut recorder = Recorder {
+        ctx,
+        validation,
+        progress: Progress::new(progress_mode),
+        output: OutputTask::spawn(output),
+        // 构建进程继承 bear_rs 的环境, 用同一个 PATH 查找编译器
+        compiler_resolver: matches
+            .get_flag("resolve_compiler")
+            .then(|| CompilerResolver::new(std::env::var_os("PATH"))),
+        exclude,
+        exclude_dirs,
+        only_under,
+        system,
+        env: env_passthrough(
+            matches
+                .get_many::<String>("env_passthrough")
+                .unwrap_or_default(),
+        ),
+        emit_compiler: matches.get_flag("emit_compiler"),
+        only_compilers: matches
+            .get_many::<String>("only_compiler")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        language: language_filter(&matches),
+        keep_probes: matches.get_flag("keep_preprocess"),
+        canonicalize_includes: matches.get_flag("canonicalize_includes"),
+        strip_flags: matches
+            .get_many::<String>("strip_flag_pattern")
+            .unwrap_or_default()
+            .map(|pattern| regex::Regex::new(&format!("^(?:{})$", pattern)).unwrap())
+            .collect(),
+        prepend_flags: matches
+            .get_many::<String>("prepend_flag")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        append_flags: matches
+            .get_many::<String>("append_flag")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        working_directory,
+        links,
+        headers: matches
+            .get_flag("include_headers")
+            .then(HeaderCollector::default),
+        dep_files: matches.get_flag("read_dep_files").then(Vec::new),
+        skip_entries: *matches.get_one::<usize>("skip_entries").unwrap(),
+        max_entries: matches.get_one::<usize>("max_entries").copied(),
+        on_limit: match matches.get_one::<String>("on_limit").unwrap().as_str() {
+            "error" => OnLimit::Error,
+            _ => OnLimit::Truncate,
+        },
+        limit_exceeded: false,
+        xcode: XcodeParser::new(matches.get_flag("xcodebuild")),
+        non_matching,
+        stats: Stats::default(),
+    };
+
+    // 读入的条目同样经过路径解析、前缀映射和检查后写出
+    if let Some(entries) = imported {
+        if let Err(err) = record_entries(&mut recorder, entries).await {
+            abort_recording(recorder, err).await;
+        }
+        let stats = finish_recorder(recorder).await;
+        report_stats(&matches, &stats, started.elapsed(), output_label.as_deref());
+        return Ok(());
+    }
 
-    let compiler_regex = Regex::new(r"(/[\w/]+)?/(cc|c\+\+|gcc|g\+\+|clang|clang\+\+)\s").unwrap();
-    let mut first_entry = true;
+    // 依次运行每条构建命令, 所有条目写入同一个数据库
+    // 每条命令都在构建目录下单独启动, 前一条命令中的 cd 不影响后面的命令
+    let build_commands = match matches.get_one::<String>("commands_file") {
+        Some(path) => read_commands_file(Path::new(path)),
+        None => vec![build_command],
+    };
+    let mut failed = None;
+    for build_command in &build_commands {
+        // 运行指定的命令并获取输出
+        let spawned = Command::new(&build_command[0])
+            .args(&build_command[1..]) // 将命令行参数传递给命令
+            .current_dir(&build_dir)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn();
+        let process = match spawned {
+            Ok(process) => process,
+            Err(err) => {
+                // 构建没有开始, 不留下空的数据库和为它新建的目录
+                recorder.discard().await;
+                remove_created_dirs(&created_dirs).await;
+                if err.kind() == io::ErrorKind::NotFound {
+                    exit_with_code(
+                        &format!("找不到构建命令 {:?}, 请检查命令名和 PATH", build_command[0]),
+                        EXIT_COMMAND_NOT_FOUND,
+                    );
+                }
+                exit_with_code(
+                    &format!("无法运行构建命令 {:?}: {}", build_command[0], err),
+                    EXIT_CANNOT_EXECUTE,
+                );
+            }
+        };
 
-    // 读取标准输出
-    let mut lines = reader.lines();
-    while let Some(line) = lines.next_line().await? {
-        process_line(&line, &compiler_regex, &mut file, &mut first_entry).await;
+        let read = read_build(&mut recorder, process, quiet, to_stdout, assume_jobserver).await;
+        let (stopped, status) = match read {
+            Ok(read) => read,
+            Err(err) => abort_recording(recorder, err).await,
+        };
+        if stopped != Stopped::Finished {
+            // 构建已经停止, 把已捕获的条目写成完整的数据库, 剩下的命令不再运行
+            recorder.progress.finish();
+            if stopped == Stopped::Interrupted {
+                warn!("构建被中断, 正在保存已捕获的 {} 条编译命令", recorder.progress.count());
+            } else if !recorder.limit_exceeded {
+                info!("已达到 --max-entries 的 {} 条, 停止构建", recorder.progress.count());
+            }
+            let stats = finish_recorder(recorder).await;
+            report_stats(&matches, &stats, started.elapsed(), output_label.as_deref());
+            if stopped == Stopped::Interrupted {
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            return Ok(());
+        }
+        if let Some(status) = status.filter(|status| !status.success()) {
+            warn!("构建命令失败: {}", status);
+            failed.get_or_insert(status);
+        }
     }
 
-    // 读取标准错误
-    let mut error_lines = error_reader.lines();
-    while let Some(line) = error_lines.next_line().await? {
-        println!("错误输出: {}", line); // 打印错误信息
+    // ninja 的条目与从输出中解析的条目一样经过过滤和路径处理
+    if let Some((ninja, ninja_dir)) = ninja_compdb {
+        match import::from_ninja_compdb(&ninja, &ninja_dir, &build_dir).await {
+            Ok(entries) => {
+                info!("从 {} -t compdb 读到 {} 条编译命令", ninja, entries.len());
+                if let Err(err) = record_entries(&mut recorder, entries).await {
+                    abort_recording(recorder, err).await;
+                }
+            }
+            Err(err) => warn!("{}", err),
+        }
+    }
+    if let Some((bazel, targets)) = bazel {
+        match import::from_bazel_aquery(&bazel, &targets, &build_dir).await {
+            Ok(entries) => {
+                info!("从 {} aquery 读到 {} 条编译命令", bazel, entries.len());
+                if let Err(err) = record_entries(&mut recorder, entries).await {
+                    abort_recording(recorder, err).await;
+                }
+            }
+            Err(err) => warn!("{}", err),
+        }
     }
 
-    let _ = file.write_all(b"\n]\n").await?;
+    let stats = finish_recorder(recorder).await;
+    report_stats(&matches, &stats, started.elapsed(), output_label.as_deref());
+
+    // 数据库照常写出, 退出码与 (第一条) 失败的构建命令一致
+    if let Some(status) = failed {
+        std::process::exit(status.code().unwrap_or(1));
+    }
 
     Ok(())
 }
 
-async fn process_line(line: &str, compiler_regex: &Regex, file: &mut File, first_entry: &mut bool) {
-    if is_compile_command(line, compiler_regex) {
-        println!("匹配的条件: {:?}", line);
-        // 使用正则表达式匹配源文件
-        let source_file_regex = Regex::new(r"(\S+\.(c|cpp|cc|cxx))\s?").unwrap();
-        let source_file = source_file_regex
-            .captures(line)
-            .and_then(|caps| caps.get(1))
-            .map_or("", |m| m.as_str())
-            .to_string();
+// 读取一条构建命令的输出并交给 recorder, 标准错误同时原样转发
+// 输出读完时等待进程结束并返回它的状态; 中途停止时结束进程, 没有状态
+async fn read_build(
+    recorder: &mut Recorder,
+    mut process: tokio::process::Child,
+    quiet: bool,
+    to_stdout: bool,
+    assume_jobserver: bool,
+) -> io::Result<(Stopped, Option<std::process::ExitStatus>)> {
+    let stdout = process.stdout.take().unwrap();
+    let reader = BufReader::new(stdout);
+    let error_reader = BufReader::new(process.stderr.take().unwrap());
 
-        let command = line;
-        let directory = std::env::current_dir()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
+    // 标准错误原样转发, 与标准输出同时读取, 避免管道写满后子进程阻塞
+    // 按字节转发, 不要求是 UTF-8
+    let stderr_task = tokio::spawn(async move {
+        let mut error_lines = error_reader.split(b'\n');
+        while let Some(line) = error_lines.next_segment().await? {
+            if !quiet {
+                echo_bytes(&line, false);
+            }
+        }
+        io::Result::Ok(())
+    });
 
-        let compile_command = CompileCommand {
-            directory,
-            command: command.to_string(),
-            file: source_file, // 使用源文件作为file字段
+    // 读取标准输出, 同时等待 Ctrl-C
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut continuation = ContinuationJoiner::default();
+    let mut joiner = assume_jobserver.then(FragmentJoiner::default);
+    // 按字节分行, 不是 UTF-8 的行 (如 Latin-1 编码的提示信息) 用替换字符解码后继续处理
+    let mut lines = reader.split(b'\n');
+    let read = async {
+        let stopped = loop {
+            let line = tokio::select! {
+                line = lines.next_segment() => line?,
+                _ = &mut ctrl_c => break Stopped::Interrupted,
+            };
+            let Some(line) = line else {
+                break Stopped::Finished;
+            };
+            if !quiet {
+                echo_bytes(&line, to_stdout);
+            }
+            let line = decode_line(line, &mut recorder.stats);
+            for line in continuation.push(line, &recorder.ctx) {
+                feed_line(recorder, joiner.as_mut(), line).await?;
+            }
+            if recorder.limit_reached() {
+                break Stopped::LimitReached;
+            }
         };
-        let json = serde_json::to_string_pretty(&compile_command).unwrap();
+        if stopped == Stopped::Finished {
+            if let Some(line) = continuation.finish() {
+                feed_line(recorder, joiner.as_mut(), line).await?;
+            }
+            if let Some(logical) = joiner.as_mut().and_then(FragmentJoiner::finish) {
+                recorder.process_line(&logical).await?;
+            }
+        }
+        io::Result::Ok(stopped)
+    };
+    let stopped = read.await;
+
+    if !matches!(stopped, Ok(Stopped::Finished)) {
+        // 停止构建, 丢弃没拼完的半行; 出错时 (如 --strict 遇到无效条目) 由调用方删除输出
+        let _ = process.start_kill();
+        let _ = process.wait().await;
+        stderr_task.abort();
+    }
+    let stopped = stopped?;
+    if stopped != Stopped::Finished {
+        return Ok((stopped, None));
+    }
+
+    stderr_task.await??;
+    let status = process.wait().await?;
+    Ok((Stopped::Finished, Some(status)))
+}
+
+// --language 只给出一种语言时才过滤, 同时给出 c 和 c++ 等于不过滤
+fn language_filter(matches: &clap::ArgMatches) -> Option<Language> {
+    let mut languages = matches
+        .get_many::<String>("language")
+        .unwrap_or_default()
+        .filter_map(|name| Language::parse(name));
+    let first = languages.next()?;
+    languages.all(|language| language == first).then_some(first)
+}
+
+// --commands-file: 每行一条构建命令, 交给 shell 执行; 忽略空行和 # 开头的注释
+fn read_commands_file(path: &Path) -> Vec<Vec<String>> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        exit_with_error(&format!("无法读取 --commands-file {}: {}", path.display(), err))
+    });
+    let commands: Vec<Vec<String>> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| shell_command(&[line]))
+        .collect();
+    if commands.is_empty() {
+        exit_with_error(&format!("--commands-file {} 中没有命令", path.display()));
+    }
+    commands
+}
+
+// 写出数据库; --on-limit=error 且条目超出上限时删除输出并报错退出
+async fn finish_recorder(recorder: Recorder) -> Stats {
+    if recorder.limit_exceeded {
+        let max_entries = recorder.max_entries.unwrap_or_default();
+        recorder.discard().await;
+        exit_with_error(&format!(
+            "编译命令超过 --max-entries 的 {} 条 (--on-limit=error), 没有写出数据库",
+            max_entries
+        ));
+    }
+    // finish 失败时已经删除了没写完的输出
+    recorder
+        .finish()
+        .await
+        .unwrap_or_else(|err| exit_with_error(&err.to_string()))
+}
+
+// 记录条目失败 (如 --strict 遇到无效条目) 时删除没写完的输出并退出
+async fn abort_recording(recorder: Recorder, err: io::Error) -> ! {
+    recorder.discard().await;
+    exit_with_error(&err.to_string());
+}
+
+// 读取构建输出的循环为什么结束
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stopped {
+    // 构建输出读完
+    Finished,
+    // 收到 Ctrl-C
+    Interrupted,
+    // 写满了 --max-entries 条
+    LimitReached,
+}
+
+// 被 Ctrl-C 中断时的退出码, 与 shell 中被 SIGINT 终止的进程一致 (128 + 2)
+const EXIT_INTERRUPTED: i32 = 130;
+
+// 构建命令无法启动时的退出码, 与 shell 一致
+const EXIT_COMMAND_NOT_FOUND: i32 = 127;
+const EXIT_CANNOT_EXECUTE: i32 = 126;
+
+// 支持的输出格式
+const OUTPUT_FORMATS: &[&str] = &["json", "jsonl", "compile-flags", "sqlite", "csv"];
+
+// 按格式创建输出, --dry-run 优先于格式, 不创建任何文件
+async fn open_output(
+    format: &str,
+    output_path: &Path,
+    to_stdout: bool,
+    dry_run: bool,
+    compact: bool,
+    csv_header: bool,
+) -> io::Result<Output> {
+    match format {
+        _ if dry_run => Ok(Output::dry_run()),
+        "compile-flags" => Ok(Output::compile_flags()),
+        "jsonl" if to_stdout => Ok(Output::jsonl_stdout()),
+        "jsonl" => Output::jsonl(output_path).await,
+        "csv" if to_stdout => Ok(Output::csv_stdout(csv_header)),
+        "csv" => Output::csv(output_path, csv_header).await,
+        "sqlite" => Output::sqlite(output_path),
+        _ if to_stdout => Ok(Output::json_stdout(compact)),
+        _ => Output::json(output_path, compact).await,
+    }
+}
+
+// 输出文件所在目录不存在时逐级创建, 返回新建的目录, 由内向外排列
+async fn create_output_parent(output_path: &Path) -> Vec<PathBuf> {
+    let parent = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => return Vec::new(),
+    };
+    let created: Vec<PathBuf> = parent
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    if let Err(err) = tokio::fs::create_dir_all(parent).await {
+        exit_with_error(&format!("无法创建输出目录 {}: {}", parent.display(), err));
+    }
+    created
+}
+
+// 删除 create_output_parent 新建的目录; 只删除空目录, 其中已有其他文件时保留
+async fn remove_created_dirs(dirs: &[PathBuf]) {
+    for dir in dirs {
+        let _ = tokio::fs::remove_dir(dir).await;
+    }
+}
+
+// 构建开始之前确认输出可以写出, 免得构建完成后才发现
+// 数据库先写到同一目录下的临时文件再改名, 所以检查的是能否在目录中创建文件, 而不是目标文件本身:
+// 只读的旧数据库同样可以被改名替换; 输出路径是目录时无法替换
+async fn preflight_output(output_path: &Path) {
+    if output_path.is_dir() {
+        exit_with_error(&format!("输出路径 {} 是一个目录", output_path.display()));
+    }
+    let temp = TempPath::new(output_path);
+    let created = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp.temp_path())
+        .await;
+    match created {
+        Ok(_) => {
+            let _ = tokio::fs::remove_file(temp.temp_path()).await;
+        }
+        // 上次中断时留下的临时文件, 能打开写入即可
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            if let Err(err) = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(temp.temp_path())
+                .await
+            {
+                exit_with_error(&format!("无法写入输出目录 {}: {}", temp.temp_path().display(), err));
+            }
+        }
+        Err(err) => {
+            exit_with_error(&format!("无法在输出目录中创建 {}: {}", temp.temp_path().display(), err));
+        }
+    }
+}
+
+// 初始化日志, 输出到标准错误, 级别由 RUST_LOG 控制, 默认 info, --quiet 时默认 warn
+fn init_logging(quiet: bool, color: bool) {
+    let default_level = if quiet { "warn" } else { "info" };
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_level));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(color)
+        .with_target(false);
+    // 分析性能时保留时间戳, 并在 span 结束时打印耗时
+    #[cfg(feature = "profiling")]
+    builder
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .init();
+    #[cfg(not(feature = "profiling"))]
+    builder.without_time().init();
+}
+
+// --color: auto 时只在标准错误是终端且没有设置 NO_COLOR 时使用颜色
+fn use_color(choice: &str) -> bool {
+    match choice {
+        "always" => true,
+        "never" => false,
+        _ => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && io::stderr().is_terminal()
+        }
+    }
+}
+
+// 原样转发子进程的一行输出; 数据库写到标准输出时改用标准错误
+fn echo_bytes(line: &[u8], to_stderr: bool) {
+    use std::io::Write;
+    let _ = if to_stderr {
+        let mut stderr = io::stderr().lock();
+        stderr.write_all(line).and_then(|_| stderr.write_all(b"\n"))
+    } else {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(line).and_then(|_| stdout.write_all(b"\n"))
+    };
+}
+
+// 把一行输出解码为字符串, 去掉行尾的 \r; 不是有效的 UTF-8 时用替换字符代替并计数
+fn decode_line(mut line: Vec<u8>, stats: &mut Stats) -> String {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    match String::from_utf8(line) {
+        Ok(line) => line,
+        Err(err) => {
+            stats.lossy_lines += 1;
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        }
+    }
+}
+
+// query 子命令: 打印源文件在已有数据库中的条目, 找不到时以非零状态退出
+fn query_database(matches: &clap::ArgMatches) {
+    let database = matches.get_one::<String>("database").unwrap();
+    let file = matches.get_one::<String>("file").unwrap();
+    let entries = database::load(Path::new(database))
+        .unwrap_or_else(|err| exit_with_error(&format!("无法读取数据库 {}: {}", database, err)));
+    let Some(entry) = database::find(&entries, file) else {
+        exit_with_error(&format!("数据库 {} 中没有 {} 的条目", database, file));
+    };
+    if matches.get_flag("arguments") {
+        let arguments = shell::split_command(&entry.command)
+            .unwrap_or_else(|err| exit_with_error(&format!("无法解析命令 {:?}: {}", entry.command, err)));
+        println!("{}", serde_json::to_string_pretty(&arguments).unwrap());
+    } else {
+        println!("{}", serde_json::to_string_pretty(entry).unwrap());
+    }
+}
+
+// 检查已有的数据库, 每个问题一行, 有错误时以 1 退出
+fn check_database(matches: &clap::ArgMatches) -> ! {
+    let database = matches.get_one::<String>("database").unwrap();
+    let problems = database::check(Path::new(database), matches.get_flag("strict"))
+        .unwrap_or_else(|err| exit_with_error(&format!("无法读取数据库 {}: {}", database, err)));
+    for problem in &problems {
+        let level = if problem.warning { "警告" } else { "错误" };
+        println!("{}: 条目 {}: {}", level, problem.index, problem.message);
+    }
+    let errors = problems.iter().filter(|problem| !problem.warning).count();
+    let warnings = problems.len() - errors;
+    if errors > 0 {
+        exit_with_error(&format!("{} 中有 {} 个错误, {} 个警告", database, errors, warnings));
+    }
+    if warnings > 0 {
+        eprintln!("{} 中有 {} 个警告", database, warnings);
+    }
+    std::process::exit(0);
+}
 
-        // 打印符合条件的编译命令
-        println!("{}", command);
+// 比较两个数据库, 每个源文件一行: + 新增, - 删除, ~ 命令改变
+// 与 diff(1) 一样, 相同时以 0 退出, 不同时以 1 退出, 出错时以 2 退出
+fn diff_databases(matches: &clap::ArgMatches) -> ! {
+    let load = |name: &str| {
+        let path = matches.get_one::<String>(name).unwrap();
+        database::load(Path::new(path)).unwrap_or_else(|err| {
+            exit_with_code(&format!("无法读取数据库 {}: {}", path, err), 2)
+        })
+    };
+    let diff = database::diff(&load("old"), &load("new"));
+    for file in &diff.added {
+        println!("+ {}", file);
+    }
+    for file in &diff.removed {
+        println!("- {}", file);
+    }
+    for changed in &diff.changed {
+        println!("~ {}", changed.file);
+        if matches.get_flag("flags") {
+            for arg in &changed.removed {
+                println!("    - {}", arg);
+            }
+            for arg in &changed.added {
+                println!("    + {}", arg);
+            }
+        }
+    }
+    if diff.is_empty() {
+        std::process::exit(0);
+    }
+    eprintln!(
+        "新增 {} 个, 删除 {} 个, 命令改变 {} 个",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+    std::process::exit(1);
+}
 
-        // 逐行写入文件，处理逗号
-        if *first_entry {
-            *first_entry = false;
+// 合并多个数据库, 每个源文件只保留一条, 读取的方式见 database::Merge
+async fn merge_databases(matches: &clap::ArgMatches) -> ! {
+    let keep_first = matches.get_flag("keep_first");
+    let mut inputs = Vec::new();
+    for input in matches.get_many::<String>("inputs").unwrap() {
+        if Path::new(input).exists() {
+            inputs.push(PathBuf::from(input));
+        } else if matches.get_flag("strict") {
+            exit_with_error(&format!("输入的数据库 {} 不存在", input));
         } else {
-            let _ = file.write_all(b",\n").await;
+            warn!("输入的数据库 {} 不存在, 跳过", input);
+        }
+    }
+
+    let merge = database::Merge::scan(inputs, keep_first)
+        .unwrap_or_else(|err| exit_with_error(&format!("无法读取数据库 {}", err)));
+
+    let output_dir = Path::new(matches.get_one::<String>("output_dir").unwrap());
+    if let Err(err) = tokio::fs::create_dir_all(output_dir).await {
+        exit_with_error(&format!("无法创建输出目录 {}: {}", output_dir.display(), err));
+    }
+    let output_path = output_dir.join("compile_commands.json");
+    let mut output = Output::json(&output_path, matches.get_flag("compact"))
+        .await
+        .unwrap_or_else(|err| {
+            exit_with_error(&format!("无法创建输出文件 {}: {}", output_path.display(), err))
+        });
+
+    // 在单独的线程中读取, 读到的条目经由通道交给这里写出
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1024);
+    let reader = tokio::task::spawn_blocking(move || {
+        merge
+            .for_each_entry(|entry| {
+                let _ = sender.blocking_send(entry);
+            })
+            .map(|()| merge.inputs())
+            .map_err(|err| format!("无法读取数据库 {}", err))
+    });
+    let mut written = 0;
+    while let Some(entry) = receiver.recv().await {
+        if let Err(err) = output.write(entry).await {
+            output.discard().await;
+            exit_with_error(&format!("无法写入 {}: {}", output_path.display(), err));
+        }
+        written += 1;
+    }
+    let read = reader
+        .await
+        .unwrap_or_else(|err| Err(format!("读取数据库的任务失败: {}", err)));
+    let merged = match read {
+        Ok(merged) => merged,
+        Err(err) => {
+            output.discard().await;
+            exit_with_error(&err);
+        }
+    };
+    if let Err(err) = output.finish().await {
+        exit_with_error(&format!("无法写入 {}: {}", output_path.display(), err));
+    }
+    info!("合并了 {} 个数据库的 {} 条编译命令到 {}", merged, written, output_path.display());
+    std::process::exit(0);
+}
+
+// 打印错误信息并退出
+fn exit_with_error(message: &str) -> ! {
+    exit_with_code(message, 1);
+}
+
+// 打印错误信息并以指定的状态退出
+fn exit_with_code(message: &str, code: i32) -> ! {
+    eprintln!("错误: {}", message);
+    std::process::exit(code);
+}
+
+// 把一行完整的构建输出交给 recorder, 并行构建时先重组被拆开的命令
+async fn feed_line(
+    recorder: &mut Recorder,
+    joiner: Option<&mut FragmentJoiner>,
+    line: String,
+) -> io::Result<()> {
+    // xcodebuild 的块不经过多行拼接, 也不按单行规则解析
+    if recorder.process_xcode_line(&line).await? {
+        return Ok(());
+    }
+    match joiner {
+        Some(joiner) => {
+            for logical in joiner.push(line, &recorder.ctx) {
+                recorder.process_line(&logical).await?;
+            }
+        }
+        None => recorder.process_line(&line).await?,
+    }
+    Ok(())
+}
+
+// 结束时在标准错误上打印一行摘要 (--quiet 时不打印), --summary-json 时另外写成 JSON
+// --stats / --stats-json 打印更详细的统计, 默认不打印
+fn report_stats(
+    matches: &clap::ArgMatches,
+    stats: &Stats,
+    elapsed: Duration,
+    output: Option<&str>,
+) {
+    if !matches.get_flag("quiet") {
+        let written = match output {
+            Some(output) => format!("写出 {} 条编译命令到 {}", stats.entries, output),
+            None => format!("匹配 {} 条编译命令 (--dry-run, 没有写出)", stats.entries),
+        };
+        let mut summary = format!(
+            "bear_rs: {}, 扫描 {} 行, 用时 {:.2}s",
+            written,
+            stats.lines,
+            elapsed.as_secs_f64()
+        );
+        if stats.compiler_lines_skipped > 0 {
+            let reasons: Vec<String> = stats
+                .top_skip_reasons(3)
+                .into_iter()
+                .map(|(reason, count)| format!("{} ({})", reason, count))
+                .collect();
+            summary.push_str(&format!(
+                "; {} 行出现了编译器但没有识别为编译命令, 主要原因: {}",
+                stats.compiler_lines_skipped,
+                reasons.join(", ")
+            ));
+        }
+        if stats.unmapped_prefixes > 0 {
+            summary.push_str(&format!(
+                "; {} 个条目的路径不在 --map-prefix 的映射范围内, 保持原样",
+                stats.unmapped_prefixes
+            ));
+        }
+        eprintln!("{}", summary);
+    }
+    if let Some(path) = matches.get_one::<String>("summary_json") {
+        let mut json = serde_json::to_value(stats).unwrap();
+        json["elapsed_seconds"] = serde_json::json!(elapsed.as_secs_f64());
+        json["output"] = serde_json::json!(output);
+        let mut content = serde_json::to_string_pretty(&json).unwrap();
+        content.push('\n');
+        if let Err(err) = std::fs::write(path, content) {
+            warn!("无法写入 --summary-json 文件 {}: {}", path, err);
         }
-        let _ = file.write_all(json.as_bytes()).await;
+    }
+    if matches.get_flag("stats") {
+        let lossy = if stats.lossy_lines > 0 {
+            format!(", {} 行不是有效的 UTF-8", stats.lossy_lines)
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "bear_rs: 扫描 {} 行, 写出 {} 条编译命令, 过滤 {} 条, 跳过 {} 条探测命令{}, 用时 {:.2}s",
+            stats.lines,
+            stats.entries,
+            stats.excluded,
+            stats.probes_skipped,
+            lossy,
+            elapsed.as_secs_f64()
+        );
+    }
+    if matches.get_flag("stats_json") {
+        let mut json = serde_json::to_value(stats).unwrap();
+        json["elapsed_seconds"] = serde_json::json!(elapsed.as_secs_f64());
+        eprintln!("{}", json);
+    }
+}
+
+// 读取 --env-passthrough 指定的环境变量, 写成 shell 的赋值形式; 没有设置的变量跳过并警告
+fn env_passthrough<'a>(names: impl Iterator<Item = &'a String>) -> Vec<String> {
+    names
+        .filter_map(|name| match std::env::var_os(name) {
+            Some(value) => Some(format!(
+                "{}={}",
+                name,
+                shell::quote(&value.to_string_lossy())
+            )),
+            None => {
+                warn!("环境变量 {} 没有设置, 不会写入编译命令", name);
+                None
+            }
+        })
+        .collect()
+}
+
+// 通过 shell 执行的命令: 只有一个参数时原样作为脚本,
+// 多个参数时用空格拼接, 含空白的参数加上引号, 其余 (如 && 和 |) 保持原样
+fn shell_command(command_and_args: &[&str]) -> Vec<String> {
+    let script = match command_and_args {
+        [script] => script.to_string(),
+        args => args
+            .iter()
+            .map(|arg| {
+                if arg.is_empty() || arg.contains(char::is_whitespace) {
+                    shell::quote(arg)
+                } else {
+                    arg.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    if cfg!(windows) {
+        vec!["cmd".to_string(), "/C".to_string(), script]
     } else {
-        // 不匹配时打印条件和行内容
-        println!("不匹配的条件: {:?}", line);
-        if !line.contains(" -c ") {
-            println!("原因: 不包含编译标志 '-c'");
-        }
-        if !line.contains(" -o ") {
-            println!("原因: 不包含输出标志 '-o'");
-        }
-        if !(line.contains(".c")
-            || line.contains(".cpp")
-            || line.contains(".cc")
-            || line.contains(".cxx"))
+        vec!["sh".to_string(), "-c".to_string(), script]
+    }
+}
+
+// 为 make / gmake 加上 VERBOSE=1 V=1, 为 cmake --build 加上 --verbose, 为 ninja / samu 加上 -v,
+// 用户已经给出的不重复添加
+// 返回添加的参数
+fn inject_verbosity(build_command: &mut Vec<String>) -> Vec<String> {
+    let Some(program) = build_command.first() else {
+        return Vec::new();
+    };
+    let program = Path::new(program)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let args = &build_command[1..];
+    let mut injected = Vec::new();
+    match program.as_str() {
+        "make" | "gmake" => {
+            for (variable, assignment) in [("VERBOSE=", "VERBOSE=1"), ("V=", "V=1")] {
+                if !args.iter().any(|arg| arg.starts_with(variable)) {
+                    injected.push(assignment.to_string());
+                }
+            }
+            build_command.extend(injected.iter().cloned());
+        }
+        "ninja" | "samu"
+            if !args.iter().any(|arg| arg == "-v" || arg == "--verbose" || arg.starts_with("-t")) =>
         {
-            println!("原因: 不包含源文件扩展名");
+            injected.push("-v".to_string());
+            build_command.insert(1, "-v".to_string());
         }
-        if line.contains("CMakeFiles") || line.contains(".make") || line.contains("target") {
-            println!("原因: 包含目标构建规则输出");
+        "cmake"
+            if args.iter().any(|arg| arg == "--build")
+                && !args.iter().any(|arg| arg == "--verbose" || arg == "-v") =>
+        {
+            injected.push("--verbose".to_string());
+            // -- 之后的参数传给底层构建工具, --verbose 要放在它前面
+            let position = build_command
+                .iter()
+                .position(|arg| arg == "--")
+                .unwrap_or(build_command.len());
+            build_command.insert(position, "--verbose".to_string());
         }
-        if !compiler_regex.is_match(line) {
-            println!("原因: 不匹配编译器命令");
+        _ => {}
+    }
+    injected
+}
+
+// 记录不是从构建输出中解析出来的条目, 写满 --max-entries 时停止
+async fn record_entries(
+    recorder: &mut Recorder,
+    entries: Vec<compile_command::CompileCommand>,
+) -> io::Result<()> {
+    for entry in entries {
+        if recorder.limit_reached() {
+            break;
         }
+        recorder
+            .record(entry.directory, &entry.command, entry.file)
+            .await?;
     }
+    Ok(())
 }
 
-// 判断一行是否为有效的编译命令
-fn is_compile_command(line: &str, compiler_regex: &Regex) -> bool {
-    // 判断是否包含编译标志 "-c" 和 "-o"
-    let contains_compile_flag = line.contains(" -c ");
-    let contains_output_flag = line.contains(" -o ");
+// 构建命令是 bazel build 时, 返回程序和要构建的目标
+// 参数值与选项分开写时 (--config opt) 无法区分, 因此只把像目标的参数当作目标:
+// 以 //、@ 或 : 开头, 或者包含 : 或 ...
+fn bazel_targets(build_command: &[String]) -> Option<(String, Vec<String>)> {
+    let (program, args) = build_command.split_first()?;
+    let name = Path::new(program).file_name()?.to_string_lossy();
+    if name != "bazel" && name != "bazelisk" {
+        return None;
+    }
+    let mut args = args.iter().skip_while(|arg| arg.starts_with('-'));
+    if args.next()? != "build" {
+        return None;
+    }
+    let targets: Vec<String> = args
+        .filter(|arg| {
+            ["//", "@", ":"].iter().any(|prefix| arg.starts_with(prefix))
+                || (!arg.starts_with('-') && (arg.contains(':') || arg.contains("...")))
+        })
+        .cloned()
+        .collect();
+    if targets.is_empty() {
+        return None;
+    }
+    Some((program.clone(), targets))
+}
 
-    // 进一步检查是否包含源文件（常见的源文件扩展名）
-    let contains_source_file = line.contains(".c")
-        || line.contains(".cpp")
-        || line.contains(".cc")
-        || line.contains(".cxx");
+// 构建命令是 ninja 或 samu 时, 返回程序和 -C 指定的构建目录 (默认当前目录)
+fn ninja_build_dir(build_command: &[String]) -> Option<(String, String)> {
+    let (program, args) = build_command.split_first()?;
+    let name = Path::new(program).file_name()?.to_string_lossy();
+    if name != "ninja" && name != "samu" {
+        return None;
+    }
+    let mut build_dir = ".".to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-C" {
+            if let Some(dir) = args.next() {
+                build_dir = dir.clone();
+            }
+        } else if let Some(dir) = arg.strip_prefix("-C") {
+            build_dir = dir.to_string();
+        }
+    }
+    Some((program.clone(), build_dir))
+}
 
-    // 使用正则表达式判断是否是编译器命令
-    compiler_regex.is_match(line)
-        && contains_compile_flag
-        && contains_output_flag
-        && contains_source_file
+// 判断是否为 make -jN 形式的并行构建, --shell 时看脚本中的第一条命令
+fn is_parallel_make(command_and_args: &[&str]) -> bool {
+    let words: Vec<&str> = match command_and_args {
+        [script] => script.split_whitespace().collect(),
+        _ => command_and_args.to_vec(),
+    };
+    let Some((command, args)) = words.split_first() else {
+        return false;
+    };
+    let program = std::path::Path::new(command)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if program != "make" && program != "gmake" {
+        return false;
+    }
+    args.iter()
+        .any(|arg| arg.starts_with("-j") || arg.starts_with("--jobs"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_command::CompileCommand;
+    use crate::testing::{recorder, TempDir, VecWriter};
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // 用 open_output 写一条条目并结束
+    async fn write_one(dir: &TempDir, format: &str, dry_run: bool) {
+        let output_path = dir.path().join("compile_commands.json");
+        let output = open_output(format, &output_path, false, dry_run, false, true).await.unwrap();
+        let mut task = OutputTask::spawn(output);
+        task.write(CompileCommand {
+            directory: dir.path().to_string_lossy().to_string(),
+            command: "cc -c a.c -o a.o".to_string(),
+            file: "a.c".to_string(),
+            compiler: None,
+            headers: None,
+        })
+        .await
+        .unwrap();
+        task.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dry_run_writes_no_file() {
+        let dir = TempDir::new();
+        for format in OUTPUT_FORMATS {
+            write_one(&dir, format, true).await;
+            assert!(file_names(dir.path()).is_empty(), "--format {}", format);
+        }
+        write_one(&dir, "json", false).await;
+        assert_eq!(file_names(dir.path()), ["compile_commands.json"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_file_runs_every_command_into_one_database() {
+        let dir = TempDir::new();
+        let commands_file = dir.write(
+            "commands.txt",
+            "# 两个子项目\n\
+             cd sub && echo \"gcc -c $PWD/a.c -o a.o\"\n\
+             \n\
+             \x20 echo \"gcc -c $PWD/b.c -o b.o\"\n",
+        );
+        let commands = read_commands_file(&commands_file);
+        assert_eq!(
+            commands,
+            [
+                shell_command(&["cd sub && echo \"gcc -c $PWD/a.c -o a.o\""]),
+                shell_command(&["echo \"gcc -c $PWD/b.c -o b.o\""]),
+            ]
+        );
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let directory = dir.path().to_string_lossy().to_string();
+        let writer = VecWriter::default();
+        let ctx = ParseContext::new(&[], &[], &[], directory.clone(), PathStyle::Native);
+        let mut recorder = recorder(ctx, &writer);
+        for command in &commands {
+            let process = Command::new(&command[0])
+                .args(&command[1..])
+                .current_dir(dir.path())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            let (stopped, status) = read_build(&mut recorder, process, true, false, false).await.unwrap();
+            assert_eq!(stopped, Stopped::Finished);
+            assert!(status.unwrap().success());
+        }
+        recorder.finish().await.unwrap();
+
+        // 前一条命令中的 cd 不影响后一条命令, 每条命令都从构建目录开始
+        let entries = writer.entries();
+        let files: Vec<&str> = entries.iter().map(|entry| entry.file.as_str()).collect();
+        assert_eq!(files, [format!("{}/sub/a.c", directory), format!("{}/b.c", directory)]);
+        assert!(entries.iter().all(|entry| entry.directory == directory));
+    }
 }
@@ -0,0 +1,735 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, trace, warn};
+
+use crate::compile_command::{compile_flags, CompileCommand};
+use crate::sqlite::SqliteWriter;
+
+// 写出一条条目或格式结尾的 future, 装箱后 CompileCommandWriter 可以作为 trait 对象使用
+pub type WriteFuture<'a> = Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>>;
+
+// 逐条写出条目的序列化格式, 只负责格式本身; 临时文件和改名由 Output 负责
+pub trait CompileCommandWriter: Send {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a>;
+
+    // 写出格式的结尾并刷新
+    fn finish(&mut self) -> WriteFuture<'_>;
+}
+
+// 输出目标: 一种格式的 writer, 写文件时再加上临时文件
+pub struct Output {
+    writer: Box<dyn CompileCommandWriter>,
+    // 先写到临时文件, 结束时改名; 写到标准输出或不写文件时没有
+    temp: Option<TempPath>,
+}
+
+impl Output {
+    // 边解析边写入 compile_commands.json 的临时文件, 结束时刷新并改名
+    // 每条条目有几次小的写入, 经过缓冲后才真正写到文件
+    pub async fn json(path: &Path, compact: bool) -> io::Result<Output> {
+        let temp = TempPath::new(path);
+        let file = File::create(&temp.temp).await?;
+        Ok(Output {
+            writer: Box::new(JsonArrayWriter::new(BufWriter::new(file), compact)),
+            temp: Some(temp),
+        })
+    }
+
+    // 输出到标准输出时先在内存中拼好整个数组, 结束时一次写出
+    pub fn json_stdout(compact: bool) -> Output {
+        Output {
+            writer: Box::new(JsonStdoutWriter(JsonArrayWriter::new(Vec::new(), compact))),
+            temp: None,
+        }
+    }
+
+    // 每行一个紧凑的 JSON 对象; 写文件时同样先写临时文件
+    pub async fn jsonl(path: &Path) -> io::Result<Output> {
+        let temp = TempPath::new(path);
+        let file = File::create(&temp.temp).await?;
+        Ok(Output {
+            writer: Box::new(JsonlWriter::new(file)),
+            temp: Some(temp),
+        })
+    }
+
+    pub fn jsonl_stdout() -> Output {
+        Output {
+            writer: Box::new(JsonlWriter::new(tokio::io::stdout())),
+            temp: None,
+        }
+    }
+
+    // file,directory,command 三列的 CSV 表格, 用于导入电子表格; 写文件时同样先写临时文件
+    pub async fn csv(path: &Path, header: bool) -> io::Result<Output> {
+        let temp = TempPath::new(path);
+        let file = BufWriter::new(File::create(&temp.temp).await?);
+        Ok(Output {
+            writer: Box::new(CsvWriter::new(file, header)),
+            temp: Some(temp),
+        })
+    }
+
+    pub fn csv_stdout(header: bool) -> Output {
+        Output {
+            writer: Box::new(CsvWriter::new(tokio::io::stdout(), header)),
+            temp: None,
+        }
+    }
+
+    // 写入 SQLite 数据库的临时文件, 结束时提交并改名
+    pub fn sqlite(path: &Path) -> io::Result<Output> {
+        let temp = TempPath::new(path);
+        let writer = SqliteWriter::create(&temp.temp)?;
+        Ok(Output {
+            writer: Box::new(writer),
+            temp: Some(temp),
+        })
+    }
+
+    // 先收集全部条目, 结束时在每个目录下写 compile_flags.txt
+    pub fn compile_flags() -> Output {
+        Output {
+            writer: Box::new(CompileFlagsWriter::default()),
+            temp: None,
+        }
+    }
+
+    // --dry-run: 不写任何文件, 只在标准错误上列出匹配到的源文件
+    pub fn dry_run() -> Output {
+        Output {
+            writer: Box::new(DryRunWriter::default()),
+            temp: None,
+        }
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    pub async fn write(&mut self, compile_command: CompileCommand) -> io::Result<()> {
+        trace!("写入条目: {}", compile_command.file);
+        self.writer.write_entry(&compile_command).await
+    }
+
+    pub async fn finish(self) -> io::Result<()> {
+        let Output { mut writer, temp } = self;
+        writer.finish().await?;
+        // 改名之前关闭文件
+        drop(writer);
+        match temp {
+            Some(temp) => temp.persist().await,
+            None => Ok(()),
+        }
+    }
+
+    // 测试中直接使用给定的 writer, 不写文件
+    #[cfg(test)]
+    pub fn from_writer(writer: impl CompileCommandWriter + 'static) -> Output {
+        Output {
+            writer: Box::new(writer),
+            temp: None,
+        }
+    }
+
+    // 没有开始构建就失败时调用, 不留下写了一半的文件
+    pub async fn discard(self) {
+        let Output { writer, temp } = self;
+        drop(writer);
+        if let Some(temp) = temp {
+            temp.discard().await;
+        }
+    }
+}
+
+// 写出任务最多积压的条目数, 写入跟不上时解析会在这里等待
+const WRITER_QUEUE: usize = 1024;
+
+// 在单独的任务中写出条目, 解析构建输出时不必等待每次写入完成
+// 只有一个写出任务, 条目按发送的顺序写出
+pub struct OutputTask {
+    sender: mpsc::Sender<CompileCommand>,
+    // 写出任务结束后已经取走
+    handle: Option<JoinHandle<(Output, io::Result<()>)>>,
+}
+
+impl OutputTask {
+    pub fn spawn(mut output: Output) -> OutputTask {
+        let (sender, mut receiver) = mpsc::channel(WRITER_QUEUE);
+        let handle = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(WRITER_QUEUE);
+            while receiver.recv_many(&mut batch, WRITER_QUEUE).await > 0 {
+                for compile_command in batch.drain(..) {
+                    if let Err(err) = output.write(compile_command).await {
+                        return (output, Err(err));
+                    }
+                }
+            }
+            (output, Ok(()))
+        });
+        OutputTask {
+            sender,
+            handle: Some(handle),
+        }
+    }
+
+    pub async fn write(&mut self, compile_command: CompileCommand) -> io::Result<()> {
+        if self.sender.send(compile_command).await.is_ok() {
+            return Ok(());
+        }
+        // 写出任务已经因为写入失败退出, 取回它的错误
+        match self.handle.take() {
+            Some(handle) => match handle.await {
+                Ok((_, Err(err))) => Err(err),
+                _ => Err(io::Error::other("写出任务已经退出")),
+            },
+            None => Err(io::Error::other("写出任务已经退出")),
+        }
+    }
+
+    // 等待写完已发送的条目, 再写出格式的结尾
+    pub async fn finish(self) -> io::Result<()> {
+        let (output, result) = self.close().await?;
+        result?;
+        output.finish().await
+    }
+
+    pub async fn discard(self) {
+        if let Ok((output, _)) = self.close().await {
+            output.discard().await;
+        }
+    }
+
+    // 关闭通道, 写出任务处理完剩下的条目后结束并交回 Output
+    async fn close(self) -> io::Result<(Output, io::Result<()>)> {
+        drop(self.sender);
+        let handle = self
+            .handle
+            .ok_or_else(|| io::Error::other("写出任务已经退出"))?;
+        handle.await.map_err(io::Error::other)
+    }
+}
+
+// 输出先写到同目录下的临时文件, 完整写完后再改名为目标文件,
+// 这样构建失败或被中断时目标位置上不会留下写了一半的数据库
+pub struct TempPath {
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl TempPath {
+    pub fn new(path: &Path) -> TempPath {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        TempPath {
+            temp: PathBuf::from(temp),
+            path: path.to_path_buf(),
+        }
+    }
+
+    // 写入用的临时文件
+    pub fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    // 同一目录下改名是原子的
+    pub async fn persist(self) -> io::Result<()> {
+        tokio::fs::rename(&self.temp, &self.path).await
+    }
+
+    // 放弃写入, 删除临时文件, 目标位置保持原样
+    pub async fn discard(self) {
+        let _ = tokio::fs::remove_file(&self.temp).await;
+    }
+}
+
+// 流式写出 JSON 数组: 每解析出一条就写一条, 自己负责括号和逗号, 没有条目时输出 []
+pub struct JsonArrayWriter<W> {
+    inner: W,
+    compact: bool,
+    entries: usize,
+}
+
+impl<W: AsyncWrite + Unpin> JsonArrayWriter<W> {
+    pub fn new(inner: W, compact: bool) -> Self {
+        JsonArrayWriter {
+            inner,
+            compact,
+            entries: 0,
+        }
+    }
+
+    // 写出任意可序列化的条目, link_commands.json 也用它
+    pub async fn write_value<T: Serialize>(&mut self, entry: &T) -> io::Result<()> {
+        // 数组的括号和逗号也按同样的格式输出, 与 serde_json 序列化整个数组的结果一致
+        let separator: &[u8] = match (self.entries == 0, self.compact) {
+            (true, true) => b"[",
+            (true, false) => b"[\n",
+            (false, true) => b",",
+            (false, false) => b",\n",
+        };
+        self.inner.write_all(separator).await?;
+        let json = to_json(entry, self.compact);
+        self.inner.write_all(json.as_bytes()).await?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    // 写出数组结尾并刷新缓冲区, 改名之前所有数据都必须已经写到文件
+    pub async fn write_end(&mut self) -> io::Result<()> {
+        let end: &[u8] = match (self.entries == 0, self.compact) {
+            (true, _) => b"[]\n",
+            (false, true) => b"]\n",
+            (false, false) => b"\n]\n",
+        };
+        self.inner.write_all(end).await?;
+        self.inner.flush().await
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> CompileCommandWriter for JsonArrayWriter<W> {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        Box::pin(self.write_value(compile_command))
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        Box::pin(self.write_end())
+    }
+}
+
+// 写到标准输出的 JSON 数组, 结束时把内存中拼好的数组一次写出
+struct JsonStdoutWriter(JsonArrayWriter<Vec<u8>>);
+
+impl CompileCommandWriter for JsonStdoutWriter {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        self.0.write_entry(compile_command)
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        Box::pin(async move {
+            self.0.write_end().await?;
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&self.0.inner).await?;
+            stdout.flush().await
+        })
+    }
+}
+
+// 每行一个紧凑的 JSON 对象, 每条都立即刷新, 读取方可以边构建边处理
+pub struct JsonlWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin + Send> JsonlWriter<W> {
+    pub fn new(inner: W) -> Self {
+        JsonlWriter { inner }
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> CompileCommandWriter for JsonlWriter<W> {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        Box::pin(async move {
+            let mut json = to_json(compile_command, true);
+            json.push('\n');
+            self.inner.write_all(json.as_bytes()).await?;
+            self.inner.flush().await
+        })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        Box::pin(self.inner.flush())
+    }
+}
+
+// 序列化单个条目, 所有格式共用; 美化格式下整体缩进一层以便放在数组里
+#[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+fn to_json<T: Serialize>(entry: &T, compact: bool) -> String {
+    trace!("序列化条目");
+    if compact {
+        return serde_json::to_string(entry).unwrap();
+    }
+    let json = serde_json::to_string_pretty(entry).unwrap();
+    // JSON 字符串里的换行都已转义, 可以放心按行缩进
+    json.lines()
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// 按 RFC 4180 写出 CSV: 每行以 CRLF 结尾, 含逗号、双引号或换行的字段加双引号, 其中的双引号写两次
+pub struct CsvWriter<W> {
+    inner: W,
+    // 还没写出的表头, 第一条条目之前写出; 没有条目时也写
+    header: bool,
+}
+
+impl<W: AsyncWrite + Unpin + Send> CsvWriter<W> {
+    pub fn new(inner: W, header: bool) -> Self {
+        CsvWriter { inner, header }
+    }
+
+    async fn write_header(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.header) {
+            self.inner.write_all(b"file,directory,command\r\n").await?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> CompileCommandWriter for CsvWriter<W> {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        Box::pin(async move {
+            self.write_header().await?;
+            let row = [
+                &compile_command.file,
+                &compile_command.directory,
+                &compile_command.command,
+            ]
+            .map(|field| csv_field(field))
+            .join(",");
+            self.inner.write_all(row.as_bytes()).await?;
+            self.inner.write_all(b"\r\n").await
+        })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        Box::pin(async move {
+            self.write_header().await?;
+            self.inner.flush().await
+        })
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// 每匹配一条打印一行源文件, 结束时打印总数, 用来调整 --exclude、--extensions 等选项
+#[derive(Default)]
+struct DryRunWriter {
+    entries: usize,
+}
+
+impl CompileCommandWriter for DryRunWriter {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        eprintln!("{}", compile_command.file);
+        self.entries += 1;
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        eprintln!(
+            "bear_rs: --dry-run 匹配到 {} 条编译命令, 没有写入数据库",
+            self.entries
+        );
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// 先收集全部条目, 结束时按目录写出 compile_flags.txt
+#[derive(Default)]
+struct CompileFlagsWriter {
+    entries: Vec<CompileCommand>,
+}
+
+impl CompileCommandWriter for CompileFlagsWriter {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        self.entries.push(compile_command.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        Box::pin(write_compile_flags(&self.entries))
+    }
+}
+
+// 按目录分组, 每个目录写一个 compile_flags.txt, 每行一个参数
+// 无法解析的命令不参与比较, 以免一个空的参数列表被当成一组参数
+async fn write_compile_flags(entries: &[CompileCommand]) -> io::Result<()> {
+    // 保持目录第一次出现的顺序
+    let mut directories: Vec<&str> = Vec::new();
+    let mut flags_by_directory: HashMap<&str, Vec<(&CompileCommand, Vec<String>)>> =
+        HashMap::new();
+    for entry in entries {
+        let flags = match compile_flags(entry) {
+            Ok(flags) => flags_of(flags),
+            Err(err) => {
+                warn!("无法解析编译命令 {:?}: {}, 已跳过", entry.command, err);
+                continue;
+            }
+        };
+        flags_by_directory
+            .entry(entry.directory.as_str())
+            .or_insert_with(|| {
+                directories.push(entry.directory.as_str());
+                Vec::new()
+            })
+            .push((entry, flags));
+    }
+
+    for directory in directories {
+        let candidates = &flags_by_directory[directory];
+        let flags = most_common(candidates.iter().map(|(_, flags)| flags));
+        if candidates.iter().any(|(_, candidate)| candidate != flags) {
+            warn!(
+                "目录 {} 下的编译参数不一致, 使用出现次数最多的一组",
+                directory
+            );
+            for (entry, candidate) in candidates {
+                if candidate != flags {
+                    debug!("{} 的编译参数没有写入 compile_flags.txt", entry.file);
+                }
+            }
+        }
+
+        let mut content = flags.join("\n");
+        content.push('\n');
+        let path = Path::new(directory).join("compile_flags.txt");
+        tokio::fs::write(&path, content).await?;
+    }
+    Ok(())
+}
+
+// 出现次数最多的一组参数, 次数相同时取最先出现的
+fn most_common<'a>(candidates: impl Iterator<Item = &'a Vec<String>> + Clone) -> &'a Vec<String> {
+    let mut counts: HashMap<&Vec<String>, usize> = HashMap::new();
+    for candidate in candidates.clone() {
+        *counts.entry(candidate).or_default() += 1;
+    }
+    let mut best = None;
+    for candidate in candidates {
+        if best.is_none_or(|best| counts[candidate] > counts[best]) {
+            best = Some(candidate);
+        }
+    }
+    best.expect("每个目录至少有一个条目")
+}
+
+// 去掉依赖文件相关的参数, 剩下的就是编译参数, 顺序保持不变
+// 分开写的 -I dir、-D name 合并为一个参数, compile_flags.txt 中每行只能是一个完整的参数
+fn flags_of(flags: Vec<String>) -> Vec<String> {
+    let mut result = Vec::with_capacity(flags.len());
+    let mut flags = flags.into_iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "-MD" | "-MMD" | "-MP" => {}
+            "-MF" | "-MT" | "-MQ" => {
+                flags.next();
+            }
+            "-I" | "-D" => result.push(format!("{}{}", flag, flags.next().unwrap_or_default())),
+            _ => result.push(flag),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, VecWriter};
+
+    fn entry(file: &str) -> CompileCommand {
+        CompileCommand {
+            directory: "/src".to_string(),
+            command: format!("cc -c {}", file),
+            file: file.to_string(),
+            compiler: None,
+            headers: None,
+        }
+    }
+
+    fn entries(count: usize) -> Vec<CompileCommand> {
+        (0..count).map(|i| entry(&format!("src/{}.c", i))).collect()
+    }
+
+    async fn write_array(entries: &[CompileCommand], compact: bool) -> String {
+        let mut writer = JsonArrayWriter::new(Vec::new(), compact);
+        for compile_command in entries {
+            writer.write_entry(compile_command).await.unwrap();
+        }
+        CompileCommandWriter::finish(&mut writer).await.unwrap();
+        String::from_utf8(writer.inner).unwrap()
+    }
+
+    fn files(json: &str) -> Vec<String> {
+        serde_json::from_str::<Vec<CompileCommand>>(json)
+            .unwrap()
+            .into_iter()
+            .map(|compile_command| compile_command.file)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn array_writer_frames_zero_one_and_many_entries() {
+        assert_eq!(write_array(&[], true).await, "[]\n");
+        let one = serde_json::to_string(&entry("src/0.c")).unwrap();
+        assert_eq!(write_array(&entries(1), true).await, format!("[{}]\n", one));
+        let many = write_array(&entries(5), true).await;
+        assert_eq!(many.matches("},{").count(), 4);
+        assert_eq!(files(&many).len(), 5);
+    }
+
+    #[tokio::test]
+    async fn compact_and_pretty_arrays_round_trip() {
+        for count in [0, 1, 3] {
+            let expected: Vec<String> = entries(count).into_iter().map(|cc| cc.file).collect();
+            for compact in [true, false] {
+                let json = write_array(&entries(count), compact).await;
+                assert_eq!(files(&json), expected, "compact={} {:?}", compact, json);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pretty_array_indents_entries_under_brackets() {
+        let json = write_array(&entries(2), false).await;
+        let expected = serde_json::to_string_pretty(&entries(2)).unwrap() + "\n";
+        assert_eq!(json, expected);
+        assert_eq!(write_array(&[], false).await, "[]\n");
+    }
+
+    #[tokio::test]
+    async fn compact_array_has_no_whitespace_between_entries() {
+        let json = write_array(&entries(2), true).await;
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.starts_with("[{") && json.ends_with("}]\n"), "{:?}", json);
+        assert!(json.contains("},{"));
+    }
+
+    #[tokio::test]
+    async fn jsonl_lines_parse_one_entry_each() {
+        let mut compile_commands = entries(3);
+        // 命令中的换行会被转义, 不会拆开一行
+        compile_commands[1].command = "cc -DMSG=\"a\nb\" -c src/1.c".to_string();
+        let mut writer = JsonlWriter::new(Vec::new());
+        for compile_command in &compile_commands {
+            writer.write_entry(compile_command).await.unwrap();
+        }
+        CompileCommandWriter::finish(&mut writer).await.unwrap();
+
+        let output = String::from_utf8(writer.inner).unwrap();
+        assert!(output.ends_with('\n'));
+        let parsed: Vec<CompileCommand> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed.len(), compile_commands.len());
+        for (parsed, expected) in parsed.iter().zip(&compile_commands) {
+            assert_eq!(parsed.file, expected.file);
+            assert_eq!(parsed.command, expected.command);
+        }
+    }
+
+    #[tokio::test]
+    async fn large_database_is_complete_after_finish() {
+        const COUNT: usize = 100_000;
+        let dir = TempDir::new();
+        let path = dir.path().join("compile_commands.json");
+        for compact in [true, false] {
+            let mut task = OutputTask::spawn(Output::json(&path, compact).await.unwrap());
+            for i in 0..COUNT {
+                task.write(entry(&format!("src/{}.c", i))).await.unwrap();
+            }
+            task.finish().await.unwrap();
+
+            let written = files(&std::fs::read_to_string(&path).unwrap());
+            assert_eq!(written.len(), COUNT);
+            assert_eq!(written[COUNT - 1], format!("src/{}.c", COUNT - 1));
+            // 改名之后不留下临时文件
+            assert!(!TempPath::new(&path).temp_path().exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn output_task_writes_entries_in_order() {
+        let writer = VecWriter::default();
+        let mut task = OutputTask::spawn(Output::from_writer(writer.clone()));
+        for file in ["a.c", "b.c", "c.c"] {
+            task.write(entry(file)).await.unwrap();
+        }
+        task.finish().await.unwrap();
+        assert_eq!(writer.files(), ["a.c", "b.c", "c.c"]);
+        assert!(writer.finished());
+    }
+
+    #[tokio::test]
+    async fn discarded_output_is_not_finished() {
+        let writer = VecWriter::default();
+        let mut task = OutputTask::spawn(Output::from_writer(writer.clone()));
+        task.write(entry("a.c")).await.unwrap();
+        task.discard().await;
+        assert_eq!(writer.files(), ["a.c"]);
+        assert!(!writer.finished());
+    }
+
+    async fn write_flags(entries: &[(&str, &str)], dir: &TempDir) -> io::Result<()> {
+        let mut output = Output::compile_flags();
+        for (directory, command) in entries {
+            let mut compile_command = entry("a.c");
+            compile_command.directory = dir.path().join(directory).to_string_lossy().to_string();
+            compile_command.command = command.to_string();
+            output.write(compile_command).await?;
+        }
+        output.finish().await
+    }
+
+    fn read_flags(dir: &TempDir, directory: &str) -> Option<String> {
+        std::fs::read_to_string(dir.path().join(directory).join("compile_flags.txt")).ok()
+    }
+
+    #[tokio::test]
+    async fn compile_flags_keep_the_order_of_the_command() {
+        let dir = TempDir::new();
+        dir.write("a/a.c", "");
+        let command = "gcc -UFOO -DFOO=2 -isystem sys -I inc -MMD -MF a.d -c a.c -o a.o -D BAR";
+        write_flags(&[("a", command)], &dir).await.unwrap();
+        assert_eq!(
+            read_flags(&dir, "a").unwrap(),
+            "-UFOO\n-DFOO=2\n-isystem\nsys\n-Iinc\n-DBAR\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn compile_flags_use_the_most_common_flags_of_each_directory() {
+        let dir = TempDir::new();
+        dir.write("a/a.c", "");
+        dir.write("b/a.c", "");
+        let entries = [
+            ("a", "gcc -O0 -c a.c"),
+            ("b", "gcc -g -c a.c"),
+            ("a", "gcc -O2 -c b.c"),
+            ("a", "gcc -O2 -c c.c"),
+            ("b", "gcc -O1 -c b.c"),
+        ];
+        write_flags(&entries, &dir).await.unwrap();
+        assert_eq!(read_flags(&dir, "a").unwrap(), "-O2\n");
+        // 次数相同时取最先出现的
+        assert_eq!(read_flags(&dir, "b").unwrap(), "-g\n");
+    }
+
+    #[tokio::test]
+    async fn compile_flags_skip_unparsable_commands() {
+        let dir = TempDir::new();
+        dir.write("a/a.c", "");
+        dir.write("b/a.c", "");
+        let entries = [
+            ("a", "gcc -c 'a.c"),
+            ("a", "gcc -c"),
+            ("a", "gcc -DX -c b.c"),
+            ("b", "gcc -c 'b.c"),
+        ];
+        write_flags(&entries, &dir).await.unwrap();
+        assert_eq!(read_flags(&dir, "a").unwrap(), "-DX\n");
+        assert_eq!(read_flags(&dir, "b"), None);
+    }
+}
@@ -0,0 +1,1190 @@
+use std::path::Path;
+
+use regex::Regex;
+use tracing::{debug, trace};
+
+use crate::compile_command::FLAGS_WITH_VALUE;
+use crate::paths::{PathStyle, PrefixMap, RelativeTo};
+use crate::shell;
+
+// 默认识别的编译器
+const DEFAULT_COMPILERS: &[&str] = &[
+    "cc", "c++", "gcc", "g++", "clang", "clang++", "as", "gas",
+    // AIX 和 Linux on POWER 上的 IBM XL 编译器, _r 是线程安全版本
+    "xlc", "xlC", "xlc++", "xlcpp", "xlc_r", "xlC_r", "xlf", "xlf90", "xlf_r", "xlf90_r",
+    // GPU 和 WebAssembly 的编译器前端
+    "hipcc", "nvcc", "emcc", "em++",
+    // Oracle Developer Studio: C 编译器是 cc/suncc, C++ 编译器是 CC
+    // 编译器名区分大小写, 在大小写敏感的文件系统上 CC 和 cc 是不同的程序
+    "CC", "suncc",
+];
+
+// 同时是常见英文单词的编译器名, 如 "treated as an error"
+// 不带路径和交叉编译前缀时, 只有后面出现了汇编源文件才算编译器
+const BARE_ASSEMBLERS: &[&str] = &["as", "gas"];
+
+// 无法用单个名字表示的默认编译器, 如带子命令的 zig cc / zig c++,
+// 以及带版本号的 clang-15、clang++-17、gcc-12、g++-12 (WASI SDK 和发行版的多版本安装)
+const DEFAULT_COMPILER_PATTERNS: &[&str] = &[
+    r"zig\s+c(?:c|\+\+)",
+    r"clang(?:\+\+)?-\d+(?:\.\d+)*",
+    r"g(?:cc|\+\+)-\d+(?:\.\d+)*",
+];
+
+// 检查命令行给出的正则是否合法
+pub fn parse_regex(pattern: &str) -> Result<String, String> {
+    Regex::new(pattern)
+        .map(|_| pattern.to_string())
+        .map_err(|err| format!("invalid regex {:?}: {}", pattern, err))
+}
+
+// 生成匹配编译器命令的正则, 第 1 个捕获组是编译器 (含路径)
+// 编译器前可以有路径和交叉编译前缀 (如 arm-none-eabi-gcc), 但必须是完整的单词, notgcc 不算
+// 路径也可以是 Windows 风格的, 如 C:\tools\clang++.exe, 后缀 .exe/.bat/.cmd 可以省略,
+// 含空格的路径会加上引号, 如 "C:\Program Files\LLVM\bin\clang.exe", 捕获组包含引号
+// 给出 patterns 时用它们代替内置的编译器名
+fn build_compiler_regex(patterns: &[String], extra_compilers: &[String]) -> Regex {
+    let names: Vec<String> = if patterns.is_empty() {
+        DEFAULT_COMPILER_PATTERNS
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .chain(DEFAULT_COMPILERS.iter().map(|name| regex::escape(name)))
+            .collect()
+    } else {
+        patterns
+            .iter()
+            .map(|pattern| format!("(?:{})", pattern))
+            .collect()
+    };
+    let names: Vec<String> = names
+        .into_iter()
+        .chain(extra_compilers.iter().map(|name| regex::escape(name)))
+        .collect();
+    let pattern = format!(
+        r#"(?:^|[^\w.+/\\:-])((?:"[^"]*[/\\]|'[^']*/)?(?:[A-Za-z]:)?(?:[\w.+-]*[/\\])*(?:[\w.]+-)*(?:{})(?i:\.exe|\.bat|\.cmd)?["']?)\s"#,
+        names.join("|")
+    );
+    Regex::new(&pattern).unwrap()
+}
+
+// 默认识别的源文件扩展名
+pub const DEFAULT_SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cpp", "cc", "cxx", "C", "s", "S",
+    // Fortran, 供 xlf 使用
+    "f", "f90", "F", "F90",
+    // HIP 和 CUDA
+    "hip", "cu",
+    // Objective-C 和 Objective-C++, Xcode 的构建中常见
+    "m", "mm",
+];
+
+// 汇编源文件的扩展名, .S 需要先预处理
+const ASSEMBLY_EXTENSIONS: &[&str] = &["s", "S"];
+
+// 解析构建输出时用到的正则和选项, 启动时构建一次
+pub struct ParseContext {
+    pub compiler_regex: Regex,
+    // 并行构建时混入同一行的构建状态输出, 如 `[ 50%]`、`[3/10]`、`make[1]:`
+    pub noise_regex: Regex,
+    // 源文件扩展名, 不含开头的点
+    pub source_extensions: Vec<String>,
+    // 编译命令的工作目录, 即条目的 directory 字段
+    pub directory: String,
+    pub path_style: PathStyle,
+    // 不存在的源文件也按路径本身解析为绝对路径
+    pub absolute_paths: bool,
+    pub prefix_map: PrefixMap,
+    // --include-pch: 保留生成预编译头的命令
+    pub include_pch: bool,
+    // 表示编译的标志, 默认只有 -c, --allow-flags 可以加上 -S、-E 等
+    pub compile_flags: Vec<String>,
+    // 是否要求有 -o, --require-output=false 时不要求
+    pub require_output: bool,
+    // --relative-to: file 改写为相对于这个目录的路径
+    pub relative_to: Option<RelativeTo>,
+}
+
+impl ParseContext {
+    // 编译器正则在一行中的所有匹配, 返回第 1 个捕获组 (编译器) 的位置
+    // 不带路径和前缀的 as / gas 后面没有汇编源文件时不算, 以免把普通的英文句子当作命令
+    pub fn compiler_matches<'a>(
+        &'a self,
+        line: &'a str,
+    ) -> impl Iterator<Item = regex::Match<'a>> + 'a {
+        self.compiler_regex
+            .captures_iter(line)
+            .map(|caps| caps.get(1).unwrap())
+            .filter(move |compiler| {
+                !BARE_ASSEMBLERS.contains(&compiler.as_str())
+                    || line[compiler.end()..]
+                        .split_whitespace()
+                        .any(|arg| is_assembly_source(arg.trim_matches(['"', '\''])))
+            })
+    }
+
+    // 一行中是否出现了编译器
+    pub fn has_compiler(&self, line: &str) -> bool {
+        self.compiler_matches(line).next().is_some()
+    }
+
+    // 是否包含源文件, 按拆分后的参数判断, 带引号或空格的路径同样能识别
+    pub fn contains_source_file(&self, line: &str) -> bool {
+        tokenize(line).iter().skip(1).any(|arg| self.is_source_arg(arg))
+    }
+
+    // 参数中是否有表示编译的标志
+    pub fn has_compile_flag(&self, args: &[String]) -> bool {
+        args.iter().skip(1).any(|arg| self.compile_flags.contains(arg))
+    }
+
+    // 参数是否是源文件: 不是选项, 且扩展名是已知的源文件扩展名
+    pub fn is_source_arg(&self, arg: &str) -> bool {
+        if arg.starts_with('-') {
+            return false;
+        }
+        let Some(ext) = Path::new(arg).extension().and_then(|ext| ext.to_str()) else {
+            return false;
+        };
+        // 扩展名不区分大小写, Windows 上的项目常用 .CPP、.Cxx 之类的写法
+        // .c 和 .C 都是已知扩展名, 所以在 Linux 上也不会误判
+        self.source_extensions
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(ext))
+    }
+
+    pub fn new(
+        compiler_patterns: &[String],
+        extra_compilers: &[String],
+        extra_source_extensions: &[String],
+        directory: String,
+        path_style: PathStyle,
+    ) -> Self {
+        let source_extensions: Vec<String> = DEFAULT_SOURCE_EXTENSIONS
+            .iter()
+            .map(|ext| ext.to_string())
+            .chain(
+                extra_source_extensions
+                    .iter()
+                    .map(|ext| ext.trim_start_matches('.').to_string()),
+            )
+            .collect();
+        ParseContext {
+            compiler_regex: build_compiler_regex(compiler_patterns, extra_compilers),
+            noise_regex: Regex::new(r"\[\s*\d+%\]|\[\d+/\d+\]|\bmake(\[\d+\])?:|\bninja:").unwrap(),
+            source_extensions,
+            directory,
+            path_style,
+            absolute_paths: false,
+            prefix_map: PrefixMap::default(),
+            include_pch: false,
+            compile_flags: vec!["-c".to_string()],
+            require_output: true,
+            relative_to: None,
+        }
+    }
+}
+
+// 从一行输出中截取编译命令片段
+// 并行构建时一行可能混有多条命令或其他任务的输出, 每个编译器出现的位置开始一段,
+// 到下一个编译器或构建状态输出为止
+// 作为选项值出现的编译器名 (如 -x c++) 不开始新的一段
+#[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+pub fn extract_commands<'a>(line: &'a str, ctx: &ParseContext) -> Vec<&'a str> {
+    let starts: Vec<usize> = ctx
+        .compiler_matches(line)
+        .map(|compiler| compiler.start())
+        .filter(|&start| {
+            let previous = line[..start].split_whitespace().next_back();
+            !previous.is_some_and(|flag| FLAGS_WITH_VALUE.contains(&flag))
+        })
+        .collect();
+    let mut commands = Vec::with_capacity(starts.len());
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(line.len());
+        let mut segment = &line[start..end];
+        if let Some(noise) = ctx.noise_regex.find(segment) {
+            segment = &segment[..noise.start()];
+        }
+        let segment = segment.trim_end();
+        if !segment.is_empty() {
+            commands.push(segment);
+        }
+    }
+    trace!("正则匹配到 {} 条命令", commands.len());
+    commands
+}
+
+// 一条命令最多由几行片段拼接而成, 防止无关输出被无限吞并
+const MAX_JOINED_FRAGMENTS: usize = 4;
+
+// 把并行构建中被拆成多行的编译命令重新拼接起来
+// 以编译器开头但还没有出现源文件的行视为不完整, 与后续非命令行拼接
+#[derive(Default)]
+pub struct FragmentJoiner {
+    pending: Option<String>,
+    fragments: usize,
+}
+
+impl FragmentJoiner {
+    pub fn push(&mut self, line: String, ctx: &ParseContext) -> Vec<String> {
+        let mut ready = Vec::new();
+        let starts_new = starts_with_command(&line, ctx) || ctx.noise_regex.is_match(&line);
+
+        if let Some(pending) = self.pending.as_mut() {
+            if !starts_new {
+                pending.push(' ');
+                pending.push_str(line.trim());
+                self.fragments += 1;
+                if ctx.contains_source_file(pending) || self.fragments >= MAX_JOINED_FRAGMENTS {
+                    ready.extend(self.finish());
+                }
+                return ready;
+            }
+            ready.extend(self.finish());
+        }
+
+        let incomplete = extract_commands(&line, ctx)
+            .last()
+            .is_some_and(|command| !ctx.contains_source_file(command));
+        if incomplete {
+            self.pending = Some(line);
+            self.fragments = 1;
+        } else {
+            ready.push(line);
+        }
+        ready
+    }
+
+    pub fn finish(&mut self) -> Option<String> {
+        self.fragments = 0;
+        self.pending.take()
+    }
+}
+
+// 一行最多由几行续行拼接而成, 防止无关输出中行尾的反斜杠吞掉后面的整个日志
+const MAX_CONTINUATION_LINES: usize = 32;
+
+// 把以反斜杠结尾的行与下一行拼接, 与 shell 一样去掉反斜杠和换行
+// 如 cmake --build . --verbose 中被拆成多行的编译命令
+// 还没有出现源文件的编译命令后面跟着缩进的行时, 也把缩进的行接到命令后面
+#[derive(Default)]
+pub struct ContinuationJoiner {
+    pending: Option<String>,
+    // pending 以反斜杠结尾, 下一行无论如何都要接上
+    escaped: bool,
+    lines: usize,
+}
+
+impl ContinuationJoiner {
+    // 返回拼接完整的行, 还要等后续的行时返回空
+    pub fn push(&mut self, line: String, ctx: &ParseContext) -> Vec<String> {
+        let mut ready = Vec::new();
+        let mut line = match self.pending.take() {
+            Some(mut pending) if self.escaped => {
+                pending.push_str(&line);
+                pending
+            }
+            Some(mut pending) if line.starts_with([' ', '\t']) => {
+                pending.push(' ');
+                pending.push_str(line.trim_start());
+                pending
+            }
+            Some(pending) => {
+                ready.push(pending);
+                self.lines = 0;
+                line
+            }
+            None => line,
+        };
+        self.lines += 1;
+        if self.lines >= MAX_CONTINUATION_LINES {
+            debug!("续行超过 {} 行, 不再拼接", MAX_CONTINUATION_LINES);
+            self.lines = 0;
+            self.escaped = false;
+            ready.push(line);
+            return ready;
+        }
+        self.escaped = ends_with_continuation(&line);
+        if self.escaped {
+            line.pop();
+            self.pending = Some(line);
+        } else if starts_with_command(&line, ctx)
+            && find_source_file(&tokenize(&line), ctx).is_none()
+        {
+            self.pending = Some(line);
+        } else {
+            self.lines = 0;
+            ready.push(line);
+        }
+        ready
+    }
+
+    pub fn finish(&mut self) -> Option<String> {
+        self.lines = 0;
+        self.escaped = false;
+        self.pending.take()
+    }
+}
+
+// 行尾有奇数个反斜杠时最后一个是续行符, 偶数个是转义的反斜杠本身
+fn ends_with_continuation(line: &str) -> bool {
+    let backslashes = line.chars().rev().take_while(|&c| c == '\\').count();
+    backslashes % 2 == 1
+}
+
+// 判断一行是否从编译器开始
+fn starts_with_command(line: &str, ctx: &ParseContext) -> bool {
+    let trimmed = line.trim_start();
+    ctx.compiler_matches(trimmed)
+        .next()
+        .is_some_and(|compiler| compiler.start() == 0)
+}
+
+// 找出编译命令中的源文件
+// 优先取 -c 后面的参数, 否则取最后一个带源文件扩展名的位置参数;
+// 选项的值 (如 -o a.c.o、-include x.cpp) 不算, 以免 -I/opt/foo.cpp-headers 之类的参数被误认
+pub fn find_source_file(args: &[String], ctx: &ParseContext) -> Option<String> {
+    let mut positional = Vec::new();
+    // 其他位置参数, 生成预编译头时输入的是头文件
+    let mut others = Vec::new();
+    let mut after_compile_flag = None;
+    let mut iter = args.iter().skip(1).peekable();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            flag if ctx.compile_flags.iter().any(|known| known == flag) => {
+                if let Some(next) = iter.peek() {
+                    if ctx.is_source_arg(next) {
+                        after_compile_flag.get_or_insert_with(|| next.to_string());
+                    }
+                }
+            }
+            "-o" => {
+                iter.next();
+            }
+            flag if FLAGS_WITH_VALUE.contains(&flag) => {
+                iter.next();
+            }
+            _ if ctx.is_source_arg(arg) => positional.push(arg),
+            _ if !arg.starts_with('-') => others.push(arg),
+            _ => {}
+        }
+    }
+    after_compile_flag
+        .or_else(|| positional.last().map(|arg| arg.to_string()))
+        .or_else(|| {
+            let header = others.last().filter(|_| ctx.include_pch && is_pch_command(args))?;
+            Some(header.to_string())
+        })
+}
+
+// 响应文件最多嵌套几层, 防止文件互相引用时死循环
+const MAX_RESPONSE_FILE_DEPTH: usize = 8;
+
+// 把命令中的响应文件 (@file) 替换为文件内容, 相对路径按 directory 解析
+// 没有可展开的响应文件时返回 None; 读不到的 @file 保留原样, 与编译器的行为一致
+pub fn expand_response_files(command: &str, directory: &str) -> Option<String> {
+    if !command.contains('@') {
+        return None;
+    }
+    let args = tokenize(command);
+    let mut expanded = Vec::with_capacity(args.len());
+    let mut changed = false;
+    for (index, arg) in args.into_iter().enumerate() {
+        if index > 0 && arg.starts_with('@') {
+            if let Some(contents) = read_response_file(&arg[1..], directory, 1) {
+                expanded.extend(contents);
+                changed = true;
+                continue;
+            }
+        }
+        expanded.push(arg);
+    }
+    changed.then(|| shell::join(&expanded))
+}
+
+fn read_response_file(path: &str, directory: &str, depth: usize) -> Option<Vec<String>> {
+    if depth > MAX_RESPONSE_FILE_DEPTH {
+        return None;
+    }
+    let content = match std::fs::read_to_string(Path::new(directory).join(path)) {
+        Ok(content) => content,
+        Err(err) => {
+            debug!("无法读取响应文件 {}: {}", path, err);
+            return None;
+        }
+    };
+    let mut args = Vec::new();
+    for arg in tokenize(&content) {
+        match arg.strip_prefix('@') {
+            Some(nested) => match read_response_file(nested, directory, depth + 1) {
+                Some(contents) => args.extend(contents),
+                None => args.push(arg),
+            },
+            None => args.push(arg),
+        }
+    }
+    Some(args)
+}
+
+// 会被构建系统用来执行命令的 shell
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh"];
+
+// 识别 sh -c '<脚本>' 形式的行, 返回脚本中用 &&、||、;、| 分开的各条命令
+// 脚本本身按 shell 规则去掉一层引号, 其中的命令保持原样
+pub fn unwrap_shell(line: &str) -> Option<Vec<String>> {
+    let words = shell::split(line.trim()).ok()?;
+    let (program, args) = words.split_first()?;
+    let name = Path::new(program).file_name()?.to_string_lossy();
+    if !SHELLS.contains(&name.as_ref()) {
+        return None;
+    }
+    // -c 可以和其他选项写在一起, 如 -ec、-xc
+    let is_options = |flags: &str| !flags.is_empty() && flags.chars().all(|c| c.is_ascii_alphabetic());
+    let mut has_c = false;
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if is_options(flags) => has_c |= flags.contains('c'),
+            _ if has_c => return Some(split_script(arg)),
+            _ => return None,
+        }
+    }
+    None
+}
+
+// 按 shell 的控制操作符拆分脚本, 引号和转义中的操作符不算
+fn split_script(script: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = script.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ';' | '&' | '|' | '\n') => {
+                // && 和 || 是两个字符
+                if (c == '&' || c == '|') && chars.peek() == Some(&c) {
+                    chars.next();
+                }
+                commands.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    commands.push(current);
+    commands
+        .into_iter()
+        .map(|command| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+// 按 shell 规则把命令拆成参数, 引号不完整时退回按空白拆分
+// 使用 Windows 路径的命令按 Windows 的规则拆分, 反斜杠不当作转义
+// 识别和提取都基于拆分后的参数, 同一条命令只拆分一次再传给各个判断函数
+pub fn tokenize(command: &str) -> Vec<String> {
+    shell::split_command(command)
+        .unwrap_or_else(|_| command.split_whitespace().map(String::from).collect())
+}
+
+// Windows 上可执行文件的后缀, 比较编译器名时去掉
+const EXECUTABLE_SUFFIXES: &[&str] = &[".exe", ".bat", ".cmd"];
+
+// 命令使用的编译器名, 去掉路径, 如 /usr/bin/arm-none-eabi-gcc -> arm-none-eabi-gcc
+// Windows 的路径同样去掉, 并去掉 .exe 等后缀, 如 C:\llvm\bin\clang.exe -> clang
+// 编译器正则不匹配时 (如导入的条目) 取命令的第一个参数
+pub fn compiler_name(command: &str, ctx: &ParseContext) -> Option<String> {
+    let compiler = match ctx.compiler_matches(command).next() {
+        Some(compiler) => compiler.as_str().to_string(),
+        None => tokenize(command)
+            .into_iter()
+            .find(|word| !shell::is_env_assignment(word))?,
+    };
+    let name = compiler.trim_matches(['"', '\'']).rsplit(['/', '\\']).next()?;
+    let name = EXECUTABLE_SUFFIXES
+        .iter()
+        .find_map(|suffix| {
+            let stem = name.len().checked_sub(suffix.len())?;
+            (name.is_char_boundary(stem) && name[stem..].eq_ignore_ascii_case(suffix))
+                .then(|| &name[..stem])
+        })
+        .unwrap_or(name);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+// 只预处理、只检查语法或查询编译器信息的参数, configure 和 CMake 的探测常用这些
+const PROBE_FLAGS: &[&str] = &[
+    "-E",
+    "-fsyntax-only",
+    "--version",
+    "-dumpmachine",
+    "-dumpversion",
+];
+
+// 判断是否为不产生目标文件的探测命令, 如 cc -E conftest.c -o conftest.i
+// -M / -MM 只输出依赖, 和 -c 一起使用时 (如 -MD 的旧写法) 仍是正常编译
+// --allow-flags 允许的标志 (如 -E) 不算探测
+pub fn is_probe_command(args: &[String], ctx: &ParseContext) -> bool {
+    let has = |flag: &str| args.iter().skip(1).any(|arg| arg == flag);
+    let allowed = |flag: &&str| ctx.compile_flags.iter().any(|known| known == flag);
+    PROBE_FLAGS.iter().filter(|flag| !allowed(flag)).any(|flag| has(flag))
+        || ((has("-M") || has("-MM")) && !has("-c"))
+}
+
+// 判断是否为生成预编译头的命令, 如 gcc -x c++-header foo.h -o foo.h.gch
+// 以 -x <语言>-header 指定语言, 或输出 .gch / .pch 文件
+pub fn is_pch_command(args: &[String]) -> bool {
+    let is_header_language = |language: &str| language.ends_with("-header");
+    let is_pch_output = |output: &str| output.ends_with(".gch") || output.ends_with(".pch");
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let found = match arg.as_str() {
+            "-x" => iter.next().is_some_and(|language| is_header_language(language)),
+            "-o" => iter.next().is_some_and(|output| is_pch_output(output)),
+            _ if arg.starts_with("-x") => is_header_language(&arg[2..]),
+            _ if arg.starts_with("-o") => is_pch_output(&arg[2..]),
+            _ => false,
+        };
+        if found {
+            return true;
+        }
+    }
+    false
+}
+
+// --language 可以选择的语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Cxx,
+}
+
+impl Language {
+    pub fn parse(name: &str) -> Option<Language> {
+        match name {
+            "c" => Some(Language::C),
+            "c++" => Some(Language::Cxx),
+            _ => None,
+        }
+    }
+}
+
+// 编译命令的语言: 有 -x 时按最后一个 -x, 否则按源文件扩展名;
+// 其他语言 (汇编、Objective-C、Fortran 等) 为 None
+// 扩展名在这里区分大小写, GCC 把 .C 当作 C++, .c 当作 C
+pub fn language_of(args: &[String], source: &str) -> Option<Language> {
+    let mut explicit = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "-x" {
+            explicit = iter.next().map(String::as_str);
+        } else if let Some(language) = arg.strip_prefix("-x") {
+            explicit = Some(language);
+        }
+    }
+    if let Some(language) = explicit {
+        return match language {
+            "c" | "c-header" => Some(Language::C),
+            "c++" | "c++-header" => Some(Language::Cxx),
+            _ => None,
+        };
+    }
+    let ext = Path::new(source).extension()?.to_str()?;
+    if ext == "c" {
+        return Some(Language::C);
+    }
+    let is_cxx = ext == "C"
+        || ["cpp", "cc", "cxx", "c++", "cp"]
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known));
+    is_cxx.then_some(Language::Cxx)
+}
+
+// 判断是否为汇编 .s / .S 文件的命令: 汇编器 as / gas (可带交叉编译前缀),
+// 或用 -x assembler / -x assembler-with-cpp 让编译器驱动充当汇编器
+pub fn is_assembler_command(command: &str, args: &[String], ctx: &ParseContext) -> bool {
+    if !find_source_file(args, ctx).is_some_and(|source| is_assembly_source(&source)) {
+        return false;
+    }
+    let compiler = compiler_name(command, ctx).unwrap_or_default();
+    compiler == "as"
+        || compiler == "gas"
+        || compiler.ends_with("-as")
+        || args.windows(2).any(|pair| pair[0] == "-x" && pair[1].starts_with("assembler"))
+        || args.iter().any(|arg| arg.starts_with("-xassembler"))
+}
+
+// 扩展名是 .s 或 .S 的文件
+fn is_assembly_source(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ASSEMBLY_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+// 判断一行是否为有效的编译命令
+// 生成预编译头的命令默认不算, --include-pch 时只要求有 -o
+// args 是 command 拆分后的参数
+pub fn is_compile_command(command: &str, args: &[String], ctx: &ParseContext) -> bool {
+    // 判断参数中是否有编译标志 "-c" (或 --allow-flags 给出的标志) 和 "-o"
+    // 直接汇编时没有 -c
+    let contains_compile_flag =
+        ctx.has_compile_flag(args) || is_assembler_command(command, args, ctx);
+    let contains_output_flag = !ctx.require_output || has_output_flag(args);
+
+    if is_pch_command(args) {
+        return ctx.include_pch && ctx.has_compiler(command) && contains_output_flag;
+    }
+
+    // 使用正则表达式判断是否是编译器命令
+    ctx.has_compiler(command)
+        && contains_compile_flag
+        && contains_output_flag
+        && args.iter().skip(1).any(|arg| ctx.is_source_arg(arg))
+}
+
+// 参数中是否有 -o
+pub fn has_output_flag(args: &[String]) -> bool {
+    args.iter().skip(1).any(|arg| arg == "-o")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn context(compiler_patterns: &[&str], extra_compilers: &[&str]) -> ParseContext {
+        let strings = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        ParseContext::new(
+            &strings(compiler_patterns),
+            &strings(extra_compilers),
+            &[],
+            "/src".to_string(),
+            PathStyle::Native,
+        )
+    }
+
+    fn default_context() -> ParseContext {
+        context(&[], &[])
+    }
+
+    fn is_compile(line: &str, ctx: &ParseContext) -> bool {
+        is_compile_command(line, &tokenize(line), ctx)
+    }
+
+    #[test]
+    fn cross_compiler_prefix_is_recognized() {
+        let ctx = default_context();
+        let line = "arm-none-eabi-gcc -c -o a.o a.c";
+        assert!(is_compile(line, &ctx));
+        assert_eq!(compiler_name(line, &ctx).as_deref(), Some("arm-none-eabi-gcc"));
+        assert!(is_compile("/opt/x/bin/aarch64-linux-gnu-g++ -c a.cpp -o a.o", &ctx));
+    }
+
+    #[test]
+    fn extra_compiler_is_added_to_the_patterns() {
+        // 给出 --compiler-regex 时内置的 emcc 不在其中, 只能由 --compiler 加上
+        let line = "emcc -c -o a.o a.c";
+        assert!(!is_compile(line, &context(&["gcc"], &[])));
+        assert!(is_compile(line, &context(&["gcc"], &["emcc"])));
+    }
+
+    #[test]
+    fn compiler_must_be_a_whole_word() {
+        let ctx = default_context();
+        assert!(!is_compile("notgcc -c a.c -o a.o", &ctx));
+        assert!(!is_compile("gccx -c a.c -o a.o", &ctx));
+    }
+
+    fn source_of(line: &str) -> Option<String> {
+        find_source_file(&tokenize(line), &default_context())
+    }
+
+    #[test]
+    fn source_is_not_taken_from_misleading_flags() {
+        assert_eq!(
+            source_of("gcc -I/opt/foo.cpp-headers -c real.c -o real.o").as_deref(),
+            Some("real.c")
+        );
+        // 选项的值看起来像源文件时也不算
+        assert_eq!(
+            source_of("gcc -isystem /opt/foo.cpp -c real.c -o real.o").as_deref(),
+            Some("real.c")
+        );
+        assert_eq!(
+            source_of("gcc -isystem /opt/foo.cpp real.c -c -o real.o").as_deref(),
+            Some("real.c")
+        );
+    }
+
+    #[test]
+    fn source_prefers_the_compile_flag_operand() {
+        assert_eq!(source_of("gcc -c a.c b.c -o a.o").as_deref(), Some("a.c"));
+        // -c 后面不是源文件时取最后一个源文件, -o 的值不算
+        assert_eq!(source_of("gcc -c -O2 a.c -o a.c.o").as_deref(), Some("a.c"));
+        assert_eq!(source_of("gcc -c -O2 -o a.o"), None);
+    }
+
+    #[test]
+    fn pch_generation_is_excluded_by_default() {
+        let mut ctx = default_context();
+        let lines = [
+            "g++ -x c++-header foo.h -o foo.h.gch",
+            "clang++ -c -xc++-header pch.hpp -o pch.hpp.pch",
+            "gcc -c stdafx.c -o stdafx.pch",
+        ];
+        for line in lines {
+            assert!(is_pch_command(&tokenize(line)), "{}", line);
+            assert!(!is_compile(line, &ctx), "{}", line);
+        }
+        assert!(!is_pch_command(&tokenize("gcc -c a.c -o a.o")));
+
+        // --include-pch 时保留, 头文件作为源文件
+        ctx.include_pch = true;
+        assert!(is_compile(lines[0], &ctx));
+        assert_eq!(
+            find_source_file(&tokenize(lines[0]), &ctx).as_deref(),
+            Some("foo.h")
+        );
+    }
+
+    #[test]
+    fn output_flag_is_optional_with_require_output_false() {
+        let mut ctx = default_context();
+        let line = "gcc -c foo.c";
+        assert!(!is_compile(line, &ctx));
+        ctx.require_output = false;
+        assert!(is_compile(line, &ctx));
+        assert!(is_compile("gcc -c foo.c -o foo.o", &ctx));
+    }
+
+    #[test]
+    fn allowed_flags_count_as_compiling() {
+        let mut ctx = default_context();
+        let assemble = "gcc -S foo.c -o foo.s";
+        let preprocess = "gcc -E foo.c -o foo.i";
+        assert!(!is_compile(assemble, &ctx));
+        ctx.compile_flags.extend(["-S".to_string(), "-E".to_string()]);
+        assert!(is_compile(assemble, &ctx));
+        assert!(is_compile(preprocess, &ctx));
+        // 允许的 -E 不再当作探测命令
+        assert!(!is_probe_command(&tokenize(preprocess), &ctx));
+        assert!(is_probe_command(&tokenize(preprocess), &default_context()));
+    }
+
+    fn join_continuations(lines: &[&str], ctx: &ParseContext) -> Vec<String> {
+        let mut joiner = ContinuationJoiner::default();
+        let mut joined: Vec<String> = lines
+            .iter()
+            .flat_map(|line| joiner.push(line.to_string(), ctx))
+            .collect();
+        joined.extend(joiner.finish());
+        joined
+    }
+
+    #[test]
+    fn backslash_continued_command_is_one_line() {
+        let ctx = default_context();
+        let joined = join_continuations(
+            &[
+                "/usr/bin/cc -DFOO -I/src/include \\",
+                "    -O2 -c /src/a.c \\",
+                "    -o a.o",
+                "next line",
+            ],
+            &ctx,
+        );
+        assert_eq!(
+            joined,
+            [
+                "/usr/bin/cc -DFOO -I/src/include     -O2 -c /src/a.c     -o a.o",
+                "next line",
+            ]
+        );
+        assert!(is_compile(&joined[0], &ctx));
+        assert_eq!(
+            find_source_file(&tokenize(&joined[0]), &ctx).as_deref(),
+            Some("/src/a.c")
+        );
+    }
+
+    #[test]
+    fn escaped_backslash_does_not_continue() {
+        let ctx = default_context();
+        let joined = join_continuations(&["echo a\\\\", "gcc -c a.c -o a.o"], &ctx);
+        assert_eq!(joined, ["echo a\\\\", "gcc -c a.c -o a.o"]);
+    }
+
+    #[test]
+    fn ibm_xl_compilers_are_recognized() {
+        let ctx = default_context();
+        let lines = [
+            ("xlc -c -O3 foo.c -o foo.o", "xlc", "foo.c"),
+            ("/opt/IBM/xlC/16.1.0/bin/xlC -c bar.C -o bar.o", "xlC", "bar.C"),
+            ("xlc_r -q64 -c baz.c -o baz.o", "xlc_r", "baz.c"),
+            ("/opt/IBM/xlf/16.1.0/bin/xlf90 -c solver.f90 -o solver.o", "xlf90", "solver.f90"),
+        ];
+        for (line, compiler, source) in lines {
+            assert!(is_compile(line, &ctx), "{}", line);
+            assert_eq!(compiler_name(line, &ctx).as_deref(), Some(compiler));
+            assert_eq!(find_source_file(&tokenize(line), &ctx).as_deref(), Some(source));
+        }
+    }
+
+    #[test]
+    fn quoted_source_paths_are_unquoted() {
+        let ctx = default_context();
+        let lines = [
+            (r#"g++ -c "src/my module/foo.cpp" -o foo.o"#, "src/my module/foo.cpp"),
+            (r"g++ -c src/my\ module/foo.cpp -o foo.o", "src/my module/foo.cpp"),
+            (r"gcc -c 'src/it'\''s/a.c' -o a.o", "src/it's/a.c"),
+            (r#"gcc -c "src/say \"hi\".c" -o a.o"#, r#"src/say "hi".c"#),
+        ];
+        for (line, source) in lines {
+            assert!(is_compile(line, &ctx), "{}", line);
+            assert_eq!(find_source_file(&tokenize(line), &ctx).as_deref(), Some(source));
+        }
+    }
+
+    #[test]
+    fn source_path_containing_another_source_name() {
+        // 按参数而不是按子串查找, foo.c 只是路径的一部分时不会被单独取出
+        assert_eq!(
+            source_of(r#"cc -c "lib/foo.c bar/foo.c" -o x.o"#).as_deref(),
+            Some("lib/foo.c bar/foo.c")
+        );
+        assert_eq!(
+            source_of("cc -MF obj/foo.c.d -c foo.c -o obj/foo.c.o").as_deref(),
+            Some("foo.c")
+        );
+        assert_eq!(
+            source_of("cc -o foo.c.o -DNAME=foo.c other/foo.c").as_deref(),
+            Some("other/foo.c")
+        );
+    }
+
+    // 编译器正则区分大小写: 在大小写敏感的文件系统上 CC 和 cc 是两个程序,
+    // 所以 CC 单独列出, 而 GCC、Clang 这类写法不会被匹配
+    #[test]
+    fn oracle_developer_studio_compilers() {
+        let ctx = default_context();
+        assert!(is_compile("CC -c foo.c -o foo.o", &ctx));
+        assert_eq!(compiler_name("CC -c foo.c -o foo.o", &ctx).as_deref(), Some("CC"));
+        assert!(is_compile("/opt/developerstudio12.6/bin/suncc -c foo.c -o foo.o", &ctx));
+        assert!(is_compile("cc -c foo.c -o foo.o", &ctx));
+        assert!(!is_compile("GCC -c foo.c -o foo.o", &ctx));
+        assert!(!is_compile("Clang -c foo.c -o foo.o", &ctx));
+    }
+
+    #[test]
+    fn source_extensions_match_case_insensitively() {
+        let ctx = default_context();
+        for (line, source) in [
+            (r"C:\llvm\bin\clang++.exe -c C:\src\Main.CPP -o Main.obj", r"C:\src\Main.CPP"),
+            ("g++ -c Widget.Cxx -o Widget.o", "Widget.Cxx"),
+            ("g++ -c legacy.C -o legacy.o", "legacy.C"),
+            ("gcc -c UPPER.C -o upper.o", "UPPER.C"),
+        ] {
+            assert!(ctx.is_source_arg(source), "{}", source);
+            assert_eq!(source_of(line).as_deref(), Some(source));
+        }
+        assert!(is_compile("g++ -c MAIN.CPP -o main.o", &ctx));
+        assert!(!ctx.is_source_arg("README.TXT"));
+    }
+
+    #[test]
+    fn compile_flag_at_end_of_line() {
+        let ctx = default_context();
+        for line in [
+            "gcc foo.c -o foo.o -c",
+            "gcc -o foo.o foo.c -c",
+            "gcc foo.c -o foo.o -c  ",
+            "gcc\tfoo.c\t-o\tfoo.o\t-c",
+        ] {
+            assert!(is_compile(line, &ctx), "{:?}", line);
+            assert_eq!(source_of(line).as_deref(), Some("foo.c"));
+        }
+        // 只比较独立的参数, -fcolor-diagnostics 中的 -c 不算
+        assert!(!is_compile("gcc foo.c -fcolor-diagnostics -o foo.o", &ctx));
+    }
+
+    #[test]
+    fn assembler_is_recognized_in_command_position() {
+        let ctx = default_context();
+        let lines = [
+            "as foo.s -o foo.o",
+            "gas -o x.o x.s",
+            "/usr/bin/as --64 start.S -o start.o",
+            "arm-none-eabi-as boot.s -o boot.o",
+        ];
+        for line in lines {
+            assert!(is_compile(line, &ctx), "{}", line);
+        }
+        assert_eq!(compiler_name(lines[2], &ctx).as_deref(), Some("as"));
+        assert_eq!(compiler_name(lines[3], &ctx).as_deref(), Some("arm-none-eabi-as"));
+    }
+
+    #[test]
+    fn assembler_names_in_prose_are_not_commands() {
+        let ctx = default_context();
+        let lines = [
+            "warning: this is treated as an error",
+            "note: a.c compiled as -c a.c -o a.o",
+            "gas prices went up",
+        ];
+        for line in lines {
+            assert!(!ctx.has_compiler(line), "{}", line);
+            assert!(extract_commands(line, &ctx).is_empty(), "{}", line);
+            // --assume-jobserver 时不会当作被拆开的命令等待后续的行
+            let mut joiner = FragmentJoiner::default();
+            assert_eq!(joiner.push(line.to_string(), &ctx), [line]);
+            assert_eq!(joiner.finish(), None);
+        }
+    }
+
+    #[test]
+    fn repeated_arch_flags_survive_tokenization() {
+        let ctx = default_context();
+        let line = "clang -arch x86_64 -arch arm64 -c a.m -o a.o";
+        let args = tokenize(line);
+        let archs: Vec<&str> = args
+            .windows(2)
+            .filter(|pair| pair[0] == "-arch")
+            .map(|pair| pair[1].as_str())
+            .collect();
+        assert_eq!(archs, ["x86_64", "arm64"]);
+        assert_eq!(shell::join(&args), line);
+        assert!(is_compile(line, &ctx));
+        assert_eq!(find_source_file(&args, &ctx).as_deref(), Some("a.m"));
+    }
+
+    #[test]
+    fn arch_value_is_not_taken_as_the_source() {
+        let ctx = default_context();
+        let line = "clang++ -arch arm64 -arch x86_64 b.mm -c -o b.o";
+        assert!(is_compile(line, &ctx));
+        assert_eq!(source_of(line).as_deref(), Some("b.mm"));
+        // 值即使带有源文件扩展名也不算
+        assert_eq!(source_of("clang -arch x.s -c a.c -o a.o").as_deref(), Some("a.c"));
+    }
+
+    #[test]
+    fn interleaved_commands_on_one_line_are_separated() {
+        let ctx = default_context();
+        let line = "gcc -c a.c -o a.o [ 50%] Building C object b.o clang -c b.c -o b.o make[2]: Leaving";
+        assert_eq!(
+            extract_commands(line, &ctx),
+            ["gcc -c a.c -o a.o", "clang -c b.c -o b.o"]
+        );
+        // 作为选项值的编译器名不开始新的一段
+        assert_eq!(
+            extract_commands("clang -x c++ -c a.cpp -o a.o", &ctx),
+            ["clang -x c++ -c a.cpp -o a.o"]
+        );
+        assert!(extract_commands("[3/10] Linking app", &ctx).is_empty());
+    }
+
+    fn push_all(joiner: &mut FragmentJoiner, lines: &[&str], ctx: &ParseContext) -> Vec<String> {
+        let mut ready: Vec<String> = lines
+            .iter()
+            .flat_map(|line| joiner.push(line.to_string(), ctx))
+            .collect();
+        ready.extend(joiner.finish());
+        ready
+    }
+
+    #[test]
+    fn partial_fragments_are_joined() {
+        let ctx = default_context();
+        let mut joiner = FragmentJoiner::default();
+        assert!(joiner.push("gcc -O2 -Iinc".to_string(), &ctx).is_empty());
+        assert_eq!(
+            joiner.push("-c a.c -o a.o".to_string(), &ctx),
+            ["gcc -O2 -Iinc -c a.c -o a.o"]
+        );
+        assert_eq!(joiner.finish(), None);
+    }
+
+    #[test]
+    fn fragments_interleaved_with_other_jobs_are_recovered() {
+        let ctx = default_context();
+        let lines = [
+            "gcc -O2 -c",
+            "a.c -o a.o",
+            "clang -c b.c -o b.o",
+            "g++ -std=c++17",
+            "[ 40%] Building CXX object c.o",
+            "gcc -c d.c -o d.o",
+        ];
+        let ready = push_all(&mut FragmentJoiner::default(), &lines, &ctx);
+        assert_eq!(
+            ready,
+            [
+                "gcc -O2 -c a.c -o a.o",
+                "clang -c b.c -o b.o",
+                // 被其他任务的输出打断的片段原样交出, 不与无关的行拼接
+                "g++ -std=c++17",
+                "[ 40%] Building CXX object c.o",
+                "gcc -c d.c -o d.o",
+            ]
+        );
+        let commands: Vec<&str> = ready
+            .iter()
+            .filter(|line| is_compile(line, &ctx))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            commands,
+            ["gcc -O2 -c a.c -o a.o", "clang -c b.c -o b.o", "gcc -c d.c -o d.o"]
+        );
+    }
+
+    #[test]
+    fn fragment_stops_after_max_joined_lines() {
+        let ctx = default_context();
+        let lines = ["gcc -O2", "-Wall", "-g", "-Werror", "-c a.c -o a.o"];
+        let ready = push_all(&mut FragmentJoiner::default(), &lines, &ctx);
+        assert_eq!(ready, ["gcc -O2 -Wall -g -Werror", "-c a.c -o a.o"]);
+    }
+
+    #[test]
+    fn shell_scripts_are_split_into_commands() {
+        assert_eq!(
+            unwrap_shell("sh -c 'cd sub && gcc -c a.c -o a.o'").unwrap(),
+            ["cd sub", "gcc -c a.c -o a.o"]
+        );
+        assert_eq!(
+            unwrap_shell(r#"/bin/bash -ec "gcc -c 'a b.c' -o x.o; echo done""#).unwrap(),
+            ["gcc -c 'a b.c' -o x.o", "echo done"]
+        );
+        // 引号中的操作符不拆分
+        assert_eq!(
+            unwrap_shell(r#"sh -c "gcc -DX='a&&b' -c a.c -o a.o | tee log""#).unwrap(),
+            ["gcc -DX='a&&b' -c a.c -o a.o", "tee log"]
+        );
+        assert_eq!(unwrap_shell("sh ./configure --prefix=/usr"), None);
+        assert_eq!(unwrap_shell("gcc -c a.c -o a.o"), None);
+    }
+
+    #[test]
+    fn libtool_compile_line_yields_the_inner_command() {
+        let ctx = default_context();
+        let line = "/bin/sh ../libtool --tag=CC --mode=compile gcc -DHAVE_CONFIG_H -I. -c foo.c -o foo.lo";
+        let commands: Vec<&str> = extract_commands(line, &ctx)
+            .into_iter()
+            .filter(|command| is_compile(command, &ctx))
+            .collect();
+        assert_eq!(commands, ["gcc -DHAVE_CONFIG_H -I. -c foo.c -o foo.lo"]);
+    }
+
+    #[test]
+    fn zig_cc_and_zig_cxx_are_recognized() {
+        let ctx = default_context();
+        let lines = [
+            "zig cc -c foo.c -o foo.o",
+            "zig c++ -std=c++17 -O2 -Iinclude -c foo.cpp -o foo.o",
+            "/opt/zig/zig cc -target aarch64-linux-musl -c src/a.c -o a.o",
+        ];
+        for line in lines {
+            assert!(is_compile(line, &ctx), "{}", line);
+        }
+        assert_eq!(compiler_name(lines[0], &ctx).as_deref(), Some("zig cc"));
+        assert_eq!(compiler_name(lines[1], &ctx).as_deref(), Some("zig c++"));
+        assert_eq!(source_of(lines[2]).as_deref(), Some("src/a.c"));
+        // 其他 zig 子命令不是编译器
+        assert!(!is_compile("zig build-obj -c foo.c -o foo.o", &ctx));
+        assert!(!ctx.has_compiler("zig build -Doptimize=ReleaseSafe"));
+    }
+
+    #[test]
+    fn versioned_compilers_are_recognized() {
+        let ctx = default_context();
+        let lines = [
+            "/opt/wasi-sdk/bin/clang-15 --target=wasm32-wasi -c a.c -o a.o",
+            "clang++-17 -c a.cpp -o a.o",
+            "gcc-12 -c a.c -o a.o",
+            "/usr/bin/g++-13.2 -c a.cpp -o a.o",
+            "x86_64-linux-gnu-gcc-12 -c a.c -o a.o",
+        ];
+        let names = ["clang-15", "clang++-17", "gcc-12", "g++-13.2", "x86_64-linux-gnu-gcc-12"];
+        for (line, name) in lines.iter().zip(names) {
+            assert!(is_compile(line, &ctx), "{}", line);
+            assert_eq!(compiler_name(line, &ctx).as_deref(), Some(name));
+        }
+        // 版本号必须是数字
+        assert!(!is_compile("clang-format -i a.c -o a.o -c", &ctx));
+        assert!(!is_compile("gcc-ar rcs liba.a a.o -c a.c -o x", &ctx));
+    }
+
+    #[test]
+    fn response_files_are_expanded_in_place() {
+        let dir = TempDir::new();
+        let directory = dir.path().to_str().unwrap();
+        dir.write("args.rsp", "-O2 @nested.rsp\n-c real.c\n");
+        dir.write("nested.rsp", "-DNDEBUG -Iinclude");
+        let line = "gcc @args.rsp -o real.o";
+        let expanded = expand_response_files(line, directory).unwrap();
+        assert_eq!(expanded, "gcc -O2 -DNDEBUG -Iinclude -c real.c -o real.o");
+        assert_eq!(source_of(&expanded).as_deref(), Some("real.c"));
+    }
+
+    #[test]
+    fn missing_response_files_are_left_as_is() {
+        let dir = TempDir::new();
+        let directory = dir.path().to_str().unwrap();
+        assert_eq!(expand_response_files("gcc -c a.c -o a.o", directory), None);
+        assert_eq!(expand_response_files("gcc @missing.rsp -c a.c", directory), None);
+        // 嵌套的响应文件不存在时保留原参数
+        dir.write("args.rsp", "-c a.c @missing.rsp");
+        assert_eq!(
+            expand_response_files("gcc @args.rsp", directory).as_deref(),
+            Some("gcc -c a.c @missing.rsp")
+        );
+    }
+
+    #[test]
+    fn probe_commands_are_detected() {
+        let ctx = default_context();
+        let is_probe = |line: &str| is_probe_command(&tokenize(line), &ctx);
+        assert!(is_probe("cc -E conftest.c -o conftest.i"));
+        assert!(is_probe("cc -fsyntax-only conftest.c"));
+        assert!(is_probe("gcc --version"));
+        assert!(is_probe("gcc -dumpmachine"));
+        assert!(is_probe("gcc -MM a.c"));
+        // -M / -MM 和 -c 一起使用时是正常编译
+        assert!(!is_probe("gcc -MM -c a.c -o a.o"));
+        assert!(!is_probe("gcc -MD -c a.c -o a.o"));
+        assert!(!is_probe("gcc -O2 -c a.c -o a.o"));
+    }
+
+    #[test]
+    fn language_follows_the_extension() {
+        let language = |line: &str, source: &str| language_of(&tokenize(line), source);
+        assert_eq!(language("gcc -c a.c", "a.c"), Some(Language::C));
+        assert_eq!(language("g++ -c a.cpp", "a.cpp"), Some(Language::Cxx));
+        assert_eq!(language("g++ -c a.cc", "a.cc"), Some(Language::Cxx));
+        assert_eq!(language("g++ -c a.C", "a.C"), Some(Language::Cxx));
+        // 其他语言不属于 C 或 C++
+        assert_eq!(language("clang -c a.m", "a.m"), None);
+        assert_eq!(language("gcc -c a.s", "a.s"), None);
+    }
+
+    #[test]
+    fn language_follows_the_last_x_flag() {
+        let language = |line: &str, source: &str| language_of(&tokenize(line), source);
+        assert_eq!(language("gcc -x c++ -c a.c", "a.c"), Some(Language::Cxx));
+        assert_eq!(language("gcc -xc -c a.cpp", "a.cpp"), Some(Language::C));
+        assert_eq!(language("gcc -x c -x c++ -c a.c", "a.c"), Some(Language::Cxx));
+        assert_eq!(language("gcc -x assembler -c a.c", "a.c"), None);
+    }
+}
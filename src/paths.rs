@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 输出中 directory 和 file 字段的路径分隔符风格
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    // 保持原样
+    Native,
+    // 反斜杠统一换成正斜杠, 盘符保持不变, 如 C:\src\a.c -> C:/src/a.c
+    Unix,
+}
+
+impl PathStyle {
+    pub fn apply(self, path: String) -> String {
+        match self {
+            PathStyle::Native => path,
+            PathStyle::Unix => path.replace('\\', "/"),
+        }
+    }
+}
+
+// 把源文件路径按编译目录解析为绝对路径, 并解析掉 `..` 和符号链接
+// 文件必须存在, 否则返回错误
+pub fn resolve_source(directory: &str, file: &str) -> io::Result<String> {
+    if is_windows_absolute(file) {
+        return Ok(file.to_string());
+    }
+    let path = Path::new(directory).join(file);
+    let resolved = path.canonicalize()?;
+    Ok(strip_verbatim(resolved.to_string_lossy().to_string()))
+}
+
+// Windows 上 canonicalize 返回 \\?\C:\... 形式的路径, 编译器和 clangd 都不需要这个前缀
+fn strip_verbatim(path: String) -> String {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        return format!(r"\\{}", unc);
+    }
+    match path.strip_prefix(r"\\?\") {
+        Some(stripped) => stripped.to_string(),
+        None => path,
+    }
+}
+
+// 不访问文件系统, 只按路径本身把源文件解析为绝对路径, 用于构建时还不存在的文件
+pub fn absolute_lexical(directory: &str, file: &str) -> String {
+    if is_windows_absolute(file) {
+        return file.to_string();
+    }
+    // 在其他系统上处理 Windows 的构建输出时, Path 不认识盘符和反斜杠, 按 Windows 的规则拼接
+    // 反过来, 目录是 POSIX 路径时文件中的反斜杠换成正斜杠
+    if !cfg!(windows) && is_windows_absolute(directory) {
+        return join_windows(directory, file);
+    }
+    let file = if cfg!(windows) || !crate::shell::is_windows_command(file) {
+        file.to_string()
+    } else {
+        file.replace('\\', "/")
+    };
+    let path = normalize_lexically(&Path::new(directory).join(file));
+    path.to_string_lossy().to_string()
+}
+
+// 用反斜杠拼接 Windows 路径并解析掉 `.` 和 `..`, 文件中的正斜杠也换成反斜杠, 不混用两种分隔符
+fn join_windows(directory: &str, file: &str) -> String {
+    let directory = directory.replace('/', "\\");
+    let (root, rest) = match directory.strip_prefix(r"\\") {
+        // UNC 路径的 \\server\share 是根, 不能被 `..` 去掉
+        Some(unc) => {
+            let mut parts = unc.splitn(3, '\\');
+            let server = parts.next().unwrap_or_default();
+            let share = parts.next().unwrap_or_default();
+            (
+                format!(r"\\{}\{}", server, share),
+                parts.next().unwrap_or_default().to_string(),
+            )
+        }
+        None => (directory[..2].to_string(), directory[2..].to_string()),
+    };
+    let file = file.replace('/', "\\");
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split('\\').chain(file.split('\\')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            other => components.push(other),
+        }
+    }
+    format!(r"{}\{}", root, components.join("\\"))
+}
+
+// 去掉路径中的 `.`, 并让 `..` 抵消前一级目录
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let popped = matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) && normalized.pop();
+                // 根目录之上没有父目录; 相对路径开头的 `..` 保留
+                if !popped && !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// 带盘符 (C:\ 或 C:/) 或 UNC (\\server\share) 的 Windows 绝对路径
+fn is_windows_absolute(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    let drive = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/');
+    drive || path.starts_with("\\\\")
+}
+
+// --map-prefix 给出的路径前缀映射, 用于把数据库搬到另一台机器 (如容器内外) 使用
+#[derive(Debug, Default)]
+pub struct PrefixMap {
+    mappings: Vec<(String, String)>,
+    // 不在任何映射范围内的条目数
+    unmapped: AtomicUsize,
+}
+
+impl PrefixMap {
+    pub fn new(mappings: Vec<(String, String)>) -> PrefixMap {
+        PrefixMap {
+            mappings,
+            unmapped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub fn unmapped(&self) -> usize {
+        self.unmapped.load(Ordering::Relaxed)
+    }
+
+    // 改写条目的 directory、file 以及命令行中出现的路径
+    pub fn apply(&self, directory: &mut String, file: &mut String, command: &mut String) {
+        if self.is_empty() {
+            return;
+        }
+        let mapped_directory = self.map_path(directory);
+        let mapped_file = self.map_path(file);
+        // file 是相对路径时跟随 directory, 不单独统计
+        let file_mapped = mapped_file.is_some() || Path::new(file.as_str()).is_relative();
+        if mapped_directory.is_none() || !file_mapped {
+            self.unmapped.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(mapped) = mapped_directory {
+            *directory = mapped;
+        }
+        if let Some(mapped) = mapped_file {
+            *file = mapped;
+        }
+        for (from, to) in &self.mappings {
+            *command = map_in_command(command, from, to);
+        }
+    }
+
+    // 整个路径按第一个匹配的前缀改写, 前缀必须落在路径分隔处
+    fn map_path(&self, path: &str) -> Option<String> {
+        self.mappings.iter().find_map(|(from, to)| {
+            let rest = path.strip_prefix(from.as_str())?;
+            let at_boundary = rest.is_empty() || rest.starts_with(['/', '\\']);
+            at_boundary.then(|| format!("{}{}", to, rest))
+        })
+    }
+}
+
+// 把路径改写为相对路径, 便于把数据库提交到仓库中
+// file 总是相对于条目自己的 directory, 使 directory 与 file 拼接后仍然是原来的源文件;
+// 只改写位于给定目录之下的路径, --relative-paths 还把 directory 改写为相对于输出目录的路径
+#[derive(Debug)]
+pub struct RelativeTo {
+    base: PathBuf,
+    // 同时改写 directory
+    directories: bool,
+    // 无法改写为相对路径 (如不在同一个盘符下) 的路径数
+    failed: AtomicUsize,
+}
+
+impl RelativeTo {
+    // --relative-to
+    pub fn new(base: &Path) -> io::Result<RelativeTo> {
+        Ok(RelativeTo {
+            base: base.canonicalize()?,
+            directories: false,
+            failed: AtomicUsize::new(0),
+        })
+    }
+
+    // --relative-paths
+    pub fn inside(base: &Path) -> io::Result<RelativeTo> {
+        Ok(RelativeTo {
+            directories: true,
+            ..RelativeTo::new(base)?
+        })
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    // 改写条目的 directory, 没有要求时保持原样; 不在 base 之下时保留为绝对路径
+    pub fn apply_directory(&self, directory: String) -> String {
+        if !self.directories {
+            return directory;
+        }
+        let absolute = normalize_lexically(Path::new(&directory));
+        match absolute.strip_prefix(&self.base) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.to_string_lossy().to_string(),
+            Err(_) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                directory
+            }
+        }
+    }
+
+    // 改写 file 或链接命令的 output, 相对路径先按 directory 转为绝对路径; 无法改写时返回绝对路径
+    // 同时改写 directory 时, directory 也必须在 base 之下, 否则它仍是绝对路径, file 没有必要改写
+    pub fn apply(&self, directory: &str, path: String) -> String {
+        let absolute = PathBuf::from(absolute_lexical(directory, &path));
+        let directory = normalize_lexically(Path::new(directory));
+        let inside = absolute.starts_with(&self.base)
+            && directory.is_absolute()
+            && (!self.directories || directory.starts_with(&self.base));
+        match inside.then(|| relative_path(&directory, &absolute)).flatten() {
+            Some(relative) => relative.to_string_lossy().to_string(),
+            None => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                absolute.to_string_lossy().to_string()
+            }
+        }
+    }
+}
+
+// 计算 path 相对于 base 的路径, 两者都是绝对路径; 根 (盘符) 不同时返回 None
+fn relative_path(base: &Path, path: &Path) -> Option<PathBuf> {
+    let mut base_components = base.components().peekable();
+    let mut path_components = path.components().peekable();
+    // 根和盘符必须相同
+    while let Some(component @ (Component::Prefix(_) | Component::RootDir)) =
+        base_components.peek().copied()
+    {
+        if path_components.next() != Some(component) {
+            return None;
+        }
+        base_components.next();
+    }
+    while base_components.peek().is_some() && base_components.peek() == path_components.peek() {
+        base_components.next();
+        path_components.next();
+    }
+    let mut relative: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+// 解析 --map-prefix 的 OLD=NEW, 去掉末尾多余的分隔符
+pub fn parse_prefix_mapping(value: &str) -> Result<(String, String), String> {
+    let (from, to) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected OLD=NEW, got {:?}", value))?;
+    let trim = |path: &str| {
+        let trimmed = path.trim_end_matches(['/', '\\']);
+        if trimmed.is_empty() { path } else { trimmed }.to_string()
+    };
+    if from.is_empty() {
+        return Err(format!("empty prefix in {:?}", value));
+    }
+    Ok((trim(from), trim(to)))
+}
+
+// 改写命令行字符串中出现的路径前缀, 带引号和不带引号的都处理,
+// 包括 -I/workspace/inc、--sysroot=/workspace 这类选项里的路径
+fn map_in_command(command: &str, from: &str, to: &str) -> String {
+    let mut result = String::with_capacity(command.len());
+    let mut last = 0;
+    for (start, _) in command.match_indices(from) {
+        if start < last {
+            continue;
+        }
+        let end = start + from.len();
+        let after_ok = match command[end..].chars().next() {
+            None => true,
+            Some(c) => c.is_whitespace() || matches!(c, '/' | '\\' | '"' | '\''),
+        };
+        if after_ok && path_starts_here(&command[..start]) {
+            result.push_str(&command[last..start]);
+            result.push_str(to);
+            last = end;
+        }
+    }
+    result.push_str(&command[last..]);
+    result
+}
+
+// 路径前面是命令开头、空白、引号、`=`、`,`, 或者紧跟在 -I 这类选项名之后
+fn path_starts_here(before: &str) -> bool {
+    let is_separator = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '=' | ',');
+    match before.chars().next_back() {
+        None => true,
+        Some(c) if is_separator(c) => true,
+        Some(c) if c.is_ascii_alphabetic() => {
+            let option = before.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+            option
+                .strip_suffix('-')
+                .is_some_and(|rest| rest.chars().next_back().is_none_or(is_separator))
+        }
+        _ => false,
+    }
+}
+
+// 把命令开头的编译器解析为绝对路径, 并跟随符号链接找到真正的可执行文件,
+// 如 cc -> /etc/alternatives/cc -> /usr/bin/gcc-12
+pub struct CompilerResolver {
+    // 查找时使用的 PATH, 与构建进程的环境一致
+    search_path: Option<OsString>,
+    // 同一个编译器只查找一次, 找不到时记为 None
+    cache: HashMap<(String, String), Option<String>>,
+    unresolved: usize,
+}
+
+impl CompilerResolver {
+    pub fn new(search_path: Option<OsString>) -> CompilerResolver {
+        CompilerResolver {
+            search_path,
+            cache: HashMap::new(),
+            unresolved: 0,
+        }
+    }
+
+    // 找不到编译器的命令数
+    pub fn unresolved(&self) -> usize {
+        self.unresolved
+    }
+
+    // 把命令的第一个参数替换为解析后的绝对路径, 找不到时保持原样
+    pub fn apply(&mut self, directory: &str, command: &mut String) {
+        let end = command.find(char::is_whitespace).unwrap_or(command.len());
+        let compiler = &command[..end];
+        // 带引号或转义的编译器路径不做处理
+        if compiler.is_empty() || compiler.contains(['\'', '"', '\\']) {
+            return;
+        }
+        let key = (directory.to_string(), compiler.to_string());
+        if !self.cache.contains_key(&key) {
+            let resolved = self.lookup(directory, compiler);
+            self.cache.insert(key.clone(), resolved);
+        }
+        match &self.cache[&key] {
+            Some(resolved) => command.replace_range(..end, &crate::shell::quote(resolved)),
+            None => self.unresolved += 1,
+        }
+    }
+
+    fn lookup(&self, directory: &str, compiler: &str) -> Option<String> {
+        // 带目录的编译器按编译目录解析, 否则在 PATH 中查找
+        let found = if compiler.contains('/') || is_windows_absolute(compiler) {
+            let path = Path::new(directory).join(compiler);
+            is_executable(&path).then_some(path)
+        } else {
+            std::env::split_paths(self.search_path.as_deref()?)
+                .flat_map(|dir| executable_names(compiler).map(move |name| dir.join(name)))
+                .find(|path| is_executable(path))
+        }?;
+        let resolved = found.canonicalize().ok()?;
+        Some(resolved.to_string_lossy().to_string())
+    }
+}
+
+// Windows 上命令可以省略 .exe 后缀
+fn executable_names(name: &str) -> impl Iterator<Item = String> {
+    let with_exe = cfg!(windows) && Path::new(name).extension().is_none();
+    std::iter::once(name.to_string()).chain(with_exe.then(|| format!("{}.exe", name)))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// 参数为头文件搜索目录的选项
+const INCLUDE_FLAGS: &[&str] = &["-I", "-isystem", "-iquote", "-idirafter"];
+
+// --canonicalize-includes: 把头文件搜索目录按 directory 解析为绝对路径, 并去掉重复的目录,
+// 保留第一次出现的位置和写法 (-Ipath 或 -I path); 命令无法拆分时返回 None
+pub fn canonicalize_includes(directory: &str, command: &str) -> Option<String> {
+    let words = crate::shell::split(command).ok()?;
+    let mut seen = std::collections::HashSet::new();
+    let mut result = Vec::with_capacity(words.len());
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let (flag, path, separate) = if INCLUDE_FLAGS.contains(&word.as_str()) {
+            match words.next() {
+                Some(path) => (word, path, true),
+                None => {
+                    result.push(word);
+                    break;
+                }
+            }
+        } else if let Some(flag) = INCLUDE_FLAGS
+            .iter()
+            .find(|flag| word.len() > flag.len() && word.starts_with(*flag))
+        {
+            let path = word[flag.len()..].to_string();
+            (flag.to_string(), path, false)
+        } else {
+            result.push(word);
+            continue;
+        };
+
+        let path =
+            resolve_source(directory, &path).unwrap_or_else(|_| absolute_lexical(directory, &path));
+        if !seen.insert((flag.clone(), path.clone())) {
+            continue;
+        }
+        if separate {
+            result.push(flag);
+            result.push(path);
+        } else {
+            result.push(format!("{}{}", flag, path));
+        }
+    }
+    Some(crate::shell::join(&result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn canonicalize_includes_resolves_and_deduplicates() {
+        let command = "gcc -Iinclude -I include -I./include -I/opt/none/include \
+                       -isystem ../third_party -isystem/third_party -c a.c -o a.o";
+        assert_eq!(
+            canonicalize_includes("/build", command).as_deref(),
+            Some("gcc -I/build/include -I/opt/none/include -isystem /third_party -c a.c -o a.o")
+        );
+    }
+
+    #[test]
+    fn canonicalize_includes_keeps_same_directory_under_different_flags() {
+        // -I 和 -isystem 的含义不同, 同一个目录各保留一次
+        let command = "gcc -Iinc -isystem inc -I inc -c a.c";
+        assert_eq!(
+            canonicalize_includes("/build", command).as_deref(),
+            Some("gcc -I/build/inc -isystem /build/inc -c a.c")
+        );
+    }
+
+    #[test]
+    fn canonicalize_includes_follows_existing_directories() {
+        let dir = TempDir::new();
+        dir.write("include/a.h", "");
+        let root = dir.path().to_string_lossy().to_string();
+        let command = format!("gcc -Iinclude -I{}/include/../include -c a.c", root);
+        assert_eq!(
+            canonicalize_includes(&root, &command),
+            Some(format!("gcc -I{}/include -c a.c", root))
+        );
+    }
+
+    #[test]
+    fn relative_paths_rewrite_only_inside_the_output_directory() {
+        let dir = TempDir::new();
+        dir.write("src/a.c", "");
+        dir.write("b.c", "");
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+        let relative = RelativeTo::inside(dir.path()).unwrap();
+        let build = format!("{}/build", root);
+
+        // directory 相对于输出目录, file 相对于 directory, 两者拼接后仍是原来的源文件
+        for (file, source) in [("../src/a.c", "src/a.c"), ("../b.c", "b.c")] {
+            let written = relative.apply(&build, format!("{}/{}", root, source));
+            assert_eq!(written, file);
+            let directory = relative.apply_directory(build.clone());
+            assert_eq!(directory, "build");
+            let resolved = dir.path().join(&directory).join(&written);
+            assert_eq!(resolved.canonicalize().unwrap(), dir.path().join(source));
+        }
+        assert_eq!(relative.apply(&root, "src/a.c".to_string()), "src/a.c");
+        assert_eq!(relative.apply_directory(root), ".");
+        assert_eq!(relative.failed(), 0);
+
+        // 不在输出目录之下时保留绝对路径并计数, 用于打印警告
+        assert_eq!(
+            relative.apply("/elsewhere", "lib/c.c".to_string()),
+            "/elsewhere/lib/c.c"
+        );
+        assert_eq!(relative.apply_directory("/elsewhere".to_string()), "/elsewhere");
+        // directory 不在输出目录之下时, 输出目录中的 file 也保留为绝对路径
+        assert_eq!(
+            relative.apply("/elsewhere", format!("{}/b.c", build)),
+            format!("{}/b.c", build)
+        );
+        assert_eq!(relative.failed(), 3);
+    }
+
+    #[test]
+    fn relative_to_writes_file_relative_to_its_directory() {
+        let dir = TempDir::new();
+        dir.write("src/x.c", "");
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+        let build = format!("{}/build", root);
+        let relative = RelativeTo::new(&dir.path().join("src")).unwrap();
+
+        // --relative-to src --working-directory build
+        let file = relative.apply(&build, format!("{}/src/x.c", root));
+        assert_eq!(file, "../src/x.c");
+        assert_eq!(relative.apply_directory(build.clone()), build);
+        let source = Path::new(&build).join(&file);
+        assert!(source.exists(), "{}", source.display());
+        assert_eq!(source.canonicalize().unwrap(), dir.path().join("src/x.c"));
+        assert_eq!(relative.failed(), 0);
+
+        // 不在 DIR 之下的路径保留为绝对路径并计数
+        assert_eq!(relative.apply(&build, "b.c".to_string()), format!("{}/b.c", build));
+        assert_eq!(relative.failed(), 1);
+    }
+
+    #[test]
+    fn relative_to_walks_up_for_paths_outside_the_directory() {
+        assert_eq!(
+            relative_path(Path::new("/repo/build"), Path::new("/repo/src/a.c")),
+            Some(PathBuf::from("../src/a.c"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/repo"), Path::new("/repo")),
+            Some(PathBuf::from("."))
+        );
+    }
+
+    #[test]
+    fn unix_path_style_replaces_backslashes_and_keeps_the_drive() {
+        let unix = |path: &str| PathStyle::Unix.apply(path.to_string());
+        assert_eq!(unix(r"C:\src\a.c"), "C:/src/a.c");
+        assert_eq!(unix(r"C:\src/sub\a.c"), "C:/src/sub/a.c");
+        assert_eq!(unix(r"\\server\share\a.c"), "//server/share/a.c");
+        assert_eq!(unix("/src/a.c"), "/src/a.c");
+    }
+
+    #[test]
+    fn native_path_style_keeps_paths_unchanged() {
+        for path in [r"C:\src\a.c", r"C:\src/sub\a.c", "/src/a.c"] {
+            assert_eq!(PathStyle::Native.apply(path.to_string()), path);
+        }
+    }
+
+    #[test]
+    fn map_in_command_rewrites_quoted_and_option_paths() {
+        let map = |command| map_in_command(command, "/workspace", "/home/me/src");
+        assert_eq!(
+            map("gcc -I/workspace/inc -isystem /workspace/sys -c /workspace/a.c"),
+            "gcc -I/home/me/src/inc -isystem /home/me/src/sys -c /home/me/src/a.c"
+        );
+        assert_eq!(
+            map(r#"gcc "-I/workspace/my inc" -DDIR='"/workspace/data"' -c '/workspace/b c.c'"#),
+            r#"gcc "-I/home/me/src/my inc" -DDIR='"/home/me/src/data"' -c '/home/me/src/b c.c'"#
+        );
+        assert_eq!(
+            map("gcc --sysroot=/workspace -Wl,-rpath,/workspace/lib -c a.c"),
+            "gcc --sysroot=/home/me/src -Wl,-rpath,/home/me/src/lib -c a.c"
+        );
+    }
+
+    #[test]
+    fn map_in_command_only_rewrites_whole_prefixes() {
+        let map = |command| map_in_command(command, "/workspace", "/home/me/src");
+        for command in [
+            "gcc -I/workspace2/inc -c a.c",
+            "gcc -I/opt/workspace/inc -c a.c",
+            "gcc -I./workspace/inc -c a.c",
+            "gcc -c a.c -o a.o",
+        ] {
+            assert_eq!(map(command), command);
+        }
+    }
+}
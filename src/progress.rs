@@ -0,0 +1,126 @@
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+// 自动刷新的进度行的刷新间隔
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+// 进度显示方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    Off,
+    // 每隔一段时间打印一行
+    Periodic(Duration),
+    // 一行自动刷新的进度
+    Live,
+}
+
+// 统计已捕获的条目数和扫描的行数, 并按设置在标准错误上显示进度
+pub struct Progress {
+    entries: Arc<AtomicUsize>,
+    lines: Arc<AtomicUsize>,
+    mode: ProgressMode,
+    reporter: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Progress {
+        let entries = Arc::new(AtomicUsize::new(0));
+        let lines = Arc::new(AtomicUsize::new(0));
+        // 标准错误不是终端时 (如重定向到日志) 不显示, 避免刷屏和写入控制字符
+        let mode = if io::stderr().is_terminal() {
+            mode
+        } else {
+            ProgressMode::Off
+        };
+        let reporter = match mode {
+            ProgressMode::Off => None,
+            ProgressMode::Periodic(period) => Some(tokio::spawn(report_periodically(
+                Arc::clone(&entries),
+                period,
+            ))),
+            ProgressMode::Live => Some(tokio::spawn(report_live(
+                Arc::clone(&entries),
+                Arc::clone(&lines),
+            ))),
+        };
+        Progress {
+            entries,
+            lines,
+            mode,
+            reporter,
+        }
+    }
+
+    // 记录一条新捕获的条目
+    pub fn record(&self) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 记录扫描了一行构建输出
+    pub fn record_line(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 已捕获的条目数
+    pub fn count(&self) -> usize {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    // 停止显示, 可以重复调用; 自动刷新的进度行被清除, 之后的日志和总结从行首开始,
+    // 重定向到文件的日志中也不会留下进度行
+    pub fn finish(&mut self) {
+        if let Some(reporter) = self.reporter.take() {
+            reporter.abort();
+            if self.mode == ProgressMode::Live {
+                eprint!("\r\x1b[K");
+            }
+        }
+    }
+}
+
+async fn report_periodically(entries: Arc<AtomicUsize>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    // 第一次 tick 立即返回, 跳过
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        eprintln!(
+            "bear_rs: 已捕获 {} 条编译命令, 构建仍在进行...",
+            entries.load(Ordering::Relaxed)
+        );
+    }
+}
+
+// 按固定间隔刷新, 不随每行输出刷新; 内容没有变化时不重画
+async fn report_live(entries: Arc<AtomicUsize>, lines: Arc<AtomicUsize>) {
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    let mut shown = String::new();
+    loop {
+        interval.tick().await;
+        let current = format!(
+            "bear_rs: {} 条编译命令 · {} 行 · {}",
+            entries.load(Ordering::Relaxed),
+            lines.load(Ordering::Relaxed),
+            format_elapsed(started.elapsed())
+        );
+        if current != shown {
+            eprint!("\r\x1b[K{}", current);
+            shown = current;
+        }
+    }
+}
+
+// 12m31s 这样的用时, 不足一分钟时只有秒, 超过一小时时加上小时
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
@@ -0,0 +1,836 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use tracing::{debug, info, trace, warn};
+
+use crate::compile_command::{self, CompileCommand};
+use crate::filter::{ExcludeFilter, RootFilter, SystemFilter};
+use crate::headers::{self, HeaderCollector};
+use crate::link::{LinkCommand, LinkRecorder};
+use crate::output::OutputTask;
+use crate::parse::{self, Language, ParseContext};
+use crate::paths::{self, CompilerResolver};
+use crate::progress::Progress;
+use crate::shell;
+use crate::xcode::{XcodeLine, XcodeParser};
+
+// 写出前是否按规范检查条目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    Off,
+    // 只打印警告
+    Warn,
+    // 遇到无效条目时中止
+    Strict,
+}
+
+// 条目数超过 --max-entries 时怎么处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnLimit {
+    // 写满 N 条后停止构建, 保留已写出的条目
+    Truncate,
+    // 出现第 N+1 条时停止构建, 不写出数据库并报错
+    Error,
+}
+
+// 等待读取依赖文件的条目, directory 和 command 是改写之前的, 用来找到依赖文件
+pub struct PendingEntry {
+    directory: String,
+    command: String,
+    compile_command: CompileCommand,
+}
+
+// 运行结束时的统计
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Stats {
+    // 扫描的构建输出行数 (重组后的逻辑行)
+    pub lines: usize,
+    // 写出的条目数
+    pub entries: usize,
+    // 被 --exclude、--exclude-dir-pattern、--only-under 和 --only-compiler 等过滤去掉的条目数
+    pub excluded: usize,
+    // 跳过的探测命令数 (-E、-fsyntax-only 等)
+    pub probes_skipped: usize,
+    // 不是有效的 UTF-8, 用替换字符解码的行数
+    pub lossy_lines: usize,
+    // 出现了编译器但没有识别为编译命令的行数
+    pub compiler_lines_skipped: usize,
+    // 这些行没有被识别的原因及次数, 一行可能有多个原因
+    pub skip_reasons: BTreeMap<String, usize>,
+    // 路径不在 --map-prefix 映射范围内, 保持原样的条目数
+    pub unmapped_prefixes: usize,
+}
+
+impl Stats {
+    // 出现次数最多的 n 个跳过原因, 次数相同时按原因排序
+    pub fn top_skip_reasons(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut reasons: Vec<(&str, usize)> = self
+            .skip_reasons
+            .iter()
+            .map(|(reason, count)| (reason.as_str(), *count))
+            .collect();
+        reasons.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        reasons.truncate(n);
+        reasons
+    }
+}
+
+// 把没有识别为编译命令的行逐行写入文本文件, 用于排查漏掉的条目
+pub struct NonMatchingWriter {
+    file: BufWriter<File>,
+    // 在每行前面加上 [原因; ...]
+    reasons: bool,
+}
+
+impl NonMatchingWriter {
+    pub async fn create(path: &Path, reasons: bool) -> io::Result<NonMatchingWriter> {
+        let file = File::create(path).await?;
+        Ok(NonMatchingWriter {
+            file: BufWriter::new(file),
+            reasons,
+        })
+    }
+
+    async fn write(&mut self, line: &str, reasons: &[String]) -> io::Result<()> {
+        let mut text = String::new();
+        if self.reasons {
+            text.push_str(&format!("[{}] ", reasons.join("; ")));
+        }
+        text.push_str(line);
+        text.push('\n');
+        self.file.write_all(text.as_bytes()).await
+    }
+
+    async fn finish(mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
+
+// 把编译命令整理成条目并写出: 解析路径、映射前缀、检查、写入和计数都在这里完成
+pub struct Recorder {
+    pub ctx: ParseContext,
+    pub validation: Validation,
+    pub progress: Progress,
+    // 在单独的任务中写出条目
+    pub output: OutputTask,
+    // --resolve-compiler 时把编译器解析为绝对路径
+    pub compiler_resolver: Option<CompilerResolver>,
+    pub exclude: ExcludeFilter,
+    pub exclude_dirs: ExcludeFilter,
+    pub only_under: RootFilter,
+    // --no-system-commands: 去掉系统目录下的源文件
+    pub system: SystemFilter,
+    // --env-passthrough 的环境变量, 以 NAME=value 的形式写在每条命令前
+    pub env: Vec<String>,
+    // --emit-compiler: 在条目中记录编译器名
+    pub emit_compiler: bool,
+    // --only-compiler: 只记录这些编译器 (不含路径) 的命令, 为空时不限制
+    pub only_compilers: Vec<String>,
+    // --language: 只记录这种语言的命令, 为 None 时不限制
+    pub language: Option<Language>,
+    // --keep-preprocess: 保留 -E、-fsyntax-only 等探测命令
+    pub keep_probes: bool,
+    // --canonicalize-includes: 头文件搜索目录转为绝对路径并去重
+    pub canonicalize_includes: bool,
+    // --strip-flag-pattern: 要去掉的参数, 每个正则都要求匹配整个参数
+    pub strip_flags: Vec<Regex>,
+    // --prepend-flag / --append-flag: 加在每条命令的编译器之后和末尾的参数
+    pub prepend_flags: Vec<String>,
+    pub append_flags: Vec<String>,
+    // --working-directory: 所有条目的 directory 都改为这个目录
+    pub working_directory: Option<String>,
+    // --link-commands: 链接命令另外写入 link_commands.json
+    pub links: Option<LinkRecorder>,
+    // --include-headers: 构建结束后从依赖文件中为头文件生成条目
+    pub headers: Option<HeaderCollector>,
+    // --read-dep-files: 依赖文件在编译之后才生成, 条目先按顺序暂存, 构建结束后补上 headers 再写出
+    pub dep_files: Option<Vec<PendingEntry>>,
+    // --skip-entries: 还要丢弃的条目数
+    pub skip_entries: usize,
+    // --max-entries: 最多写出的条目数
+    pub max_entries: Option<usize>,
+    pub on_limit: OnLimit,
+    // --on-limit=error 时已经出现了超出上限的条目
+    pub limit_exceeded: bool,
+    // xcodebuild 输出中的 CompileC 块
+    pub xcode: XcodeParser,
+    // --print-non-matching: 没有识别为编译命令的行另外写入文件
+    pub non_matching: Option<NonMatchingWriter>,
+    pub stats: Stats,
+}
+
+impl Recorder {
+    // 处理属于 xcodebuild 块的行, 返回 false 表示这一行要按普通输出处理
+    pub async fn process_xcode_line(&mut self, line: &str) -> io::Result<bool> {
+        let command = match self.xcode.push(line) {
+            XcodeLine::Other => return Ok(false),
+            XcodeLine::Consumed => None,
+            XcodeLine::Command(command) => Some(command),
+        };
+        self.stats.lines += 1;
+        self.progress.record_line();
+        if let Some(command) = command {
+            let directory = command
+                .directory
+                .unwrap_or_else(|| self.ctx.directory.clone());
+            self.record(directory, &command.command, command.source)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    // 从构建输出的一行中找出所有编译命令并记录
+    #[cfg_attr(
+        feature = "profiling",
+        tracing::instrument(level = "trace", skip_all, fields(line = self.stats.lines + 1))
+    )]
+    pub async fn process_line(&mut self, line: &str) -> io::Result<()> {
+        self.stats.lines += 1;
+        self.progress.record_line();
+        // sh -c '...' 的脚本拆成单独的命令再识别, 脚本中的 cd 改变后续命令的目录
+        let Some(script) = parse::unwrap_shell(line) else {
+            let directory = self.ctx.directory.clone();
+            return self.process_commands(line, directory).await;
+        };
+        let mut directory = self.ctx.directory.clone();
+        for command in script {
+            match command.strip_prefix("cd ") {
+                Some(target) => {
+                    let target = shell::split_command(target).unwrap_or_default();
+                    if let Some(target) = target.first() {
+                        directory = paths::absolute_lexical(&directory, target);
+                    }
+                }
+                None => self.process_commands(&command, directory.clone()).await?,
+            }
+        }
+        Ok(())
+    }
+
+    // 找出一段命令中的所有编译命令并记录, directory 是命令执行的目录
+    async fn process_commands(&mut self, line: &str, directory: String) -> io::Result<()> {
+        if let Some(links) = &mut self.links {
+            for (command, output) in links.find(line, &self.ctx) {
+                let mut link_command = LinkCommand {
+                    directory: self
+                        .working_directory
+                        .clone()
+                        .unwrap_or_else(|| directory.clone()),
+                    command: command.to_string(),
+                    output,
+                };
+                self.ctx.prefix_map.apply(
+                    &mut link_command.directory,
+                    &mut link_command.output,
+                    &mut link_command.command,
+                );
+                if let Some(relative_to) = &self.ctx.relative_to {
+                    link_command.output =
+                        relative_to.apply(&link_command.directory, link_command.output);
+                    link_command.directory = relative_to.apply_directory(link_command.directory);
+                }
+                link_command.directory = self.ctx.path_style.apply(link_command.directory);
+                link_command.output = self.ctx.path_style.apply(link_command.output);
+                debug!("链接命令: {:?}", command);
+                links.write(&link_command).await?;
+            }
+        }
+        let mut matched = false;
+        for command in parse::extract_commands(line, &self.ctx) {
+            // 参数放在响应文件中时先展开, 否则找不到 -c、-o 和源文件
+            let expanded = parse::expand_response_files(command, &directory);
+            let command = expanded.as_deref().unwrap_or(command);
+            let args = parse::tokenize(command);
+            if !parse::is_compile_command(command, &args, &self.ctx) {
+                continue;
+            }
+            matched = true;
+            if !self.keep_probes && parse::is_probe_command(&args, &self.ctx) {
+                debug!("跳过探测命令: {:?}", command);
+                self.stats.probes_skipped += 1;
+                continue;
+            }
+            if !self.only_compilers.is_empty() {
+                let compiler = parse::compiler_name(command, &self.ctx).unwrap_or_default();
+                if !self.only_compilers.contains(&compiler) {
+                    trace!(
+                        "编译器 {} 不在 --only-compiler 中, 跳过: {:?}",
+                        compiler,
+                        command
+                    );
+                    self.stats.excluded += 1;
+                    continue;
+                }
+            }
+            debug!("匹配的条件: {:?}", command);
+            let source_file = parse::find_source_file(&args, &self.ctx).unwrap_or_default();
+            if let Some(language) = self.language {
+                if parse::language_of(&args, &source_file) != Some(language) {
+                    trace!("不是 --language 指定的语言, 跳过: {:?}", command);
+                    self.stats.excluded += 1;
+                    continue;
+                }
+            }
+            self.record(directory.clone(), command, source_file).await?;
+        }
+
+        if !matched {
+            // 不匹配时打印条件和行内容
+            trace!("不匹配的条件: {:?}", line);
+            let reasons = self.mismatch_reasons(line);
+            for reason in &reasons {
+                trace!("原因: {}", reason);
+            }
+            if self.ctx.has_compiler(line) {
+                self.stats.compiler_lines_skipped += 1;
+                for reason in &reasons {
+                    *self.stats.skip_reasons.entry(reason.clone()).or_default() += 1;
+                }
+            }
+            if let Some(non_matching) = &mut self.non_matching {
+                non_matching.write(line, &reasons).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // 一行没有被识别为编译命令的原因
+    fn mismatch_reasons(&self, line: &str) -> Vec<String> {
+        let args = parse::tokenize(line);
+        let mut reasons = Vec::new();
+        if !self.ctx.has_compiler(line) {
+            reasons.push("不匹配编译器命令".to_string());
+        }
+        if !self.ctx.has_compile_flag(&args) {
+            reasons.push(format!("不包含编译标志 {:?}", self.ctx.compile_flags));
+        }
+        if self.ctx.require_output && !parse::has_output_flag(&args) {
+            reasons.push("不包含输出标志 '-o'".to_string());
+        }
+        if !args.iter().any(|arg| self.ctx.is_source_arg(arg)) {
+            reasons.push("不包含源文件扩展名".to_string());
+        }
+        if line.contains("CMakeFiles") || line.contains(".make") || line.contains("target") {
+            reasons.push("包含目标构建规则输出".to_string());
+        }
+        if parse::is_pch_command(&args) {
+            reasons.push("生成预编译头".to_string());
+        }
+        reasons
+    }
+
+    // 记录一条在 directory 下执行的编译命令, source_file 是命令中出现的源文件路径
+    pub async fn record(
+        &mut self,
+        directory: String,
+        command: &str,
+        source_file: String,
+    ) -> io::Result<()> {
+        let ctx = &self.ctx;
+        let directory = self.working_directory.clone().unwrap_or(directory);
+        // 在解析路径之前检查, 否则空的 file 会被解析成 directory 本身
+        if self.validation != Validation::Off {
+            let errors = compile_command::validate_entry(&CompileCommand {
+                directory: directory.clone(),
+                command: command.to_string(),
+                file: source_file.clone(),
+                compiler: None,
+                headers: None,
+            });
+            if !errors.is_empty() {
+                let reasons: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                warn!("无效的条目 {:?}: {}", command, reasons.join(", "));
+                if self.validation == Validation::Strict {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("--strict: 条目 {:?} 无效", command),
+                    ));
+                }
+            }
+        }
+        let captured = (self.headers.is_some() || self.dep_files.is_some())
+            .then(|| (directory.clone(), source_file.clone()));
+        // 相对路径按 directory 解析为绝对路径, 文件不存在时 (如生成的文件) 保留原样
+        let source_file = match paths::resolve_source(&directory, &source_file) {
+            Ok(resolved) => resolved,
+            Err(err) if ctx.absolute_paths => {
+                debug!(
+                    "无法解析源文件 {:?}, 按路径本身转为绝对路径: {}",
+                    source_file, err
+                );
+                paths::absolute_lexical(&directory, &source_file)
+            }
+            Err(err) => {
+                debug!("无法解析源文件 {:?}, 保留原始路径: {}", source_file, err);
+                source_file
+            }
+        };
+
+        if self.exclude.excludes(&directory, &source_file) {
+            debug!("按 --exclude 排除 {:?}", source_file);
+            self.stats.excluded += 1;
+            return Ok(());
+        }
+        if self.exclude_dirs.excludes_directory(&directory) {
+            debug!("按 --exclude-dir-pattern 排除目录 {:?} 中的命令", directory);
+            self.stats.excluded += 1;
+            return Ok(());
+        }
+        if !self.only_under.keeps(&directory, &source_file) {
+            debug!("源文件 {:?} 不在 --only-under 目录之下, 跳过", source_file);
+            self.stats.excluded += 1;
+            return Ok(());
+        }
+        if self.system.drops(&directory, &source_file) {
+            debug!("源文件 {:?} 位于系统目录下, 跳过", source_file);
+            self.stats.excluded += 1;
+            return Ok(());
+        }
+
+        let mut compile_command = CompileCommand {
+            directory,
+            command: command.to_string(),
+            file: source_file, // 使用源文件作为file字段
+            compiler: if self.emit_compiler {
+                parse::compiler_name(command, ctx)
+            } else {
+                None
+            },
+            headers: None,
+        };
+        if let Some(resolver) = &mut self.compiler_resolver {
+            resolver.apply(&compile_command.directory, &mut compile_command.command);
+        }
+        if self.canonicalize_includes {
+            match paths::canonicalize_includes(&compile_command.directory, &compile_command.command)
+            {
+                Some(canonical) => compile_command.command = canonical,
+                None => debug!("无法拆分命令, 保留原样: {:?}", compile_command.command),
+            }
+        }
+        if !self.strip_flags.is_empty() {
+            match compile_command::strip_flags(&compile_command.command, &self.strip_flags) {
+                Some((command, stripped)) => {
+                    if !stripped.is_empty() {
+                        debug!("按 --strip-flag-pattern 去掉参数 {:?}", stripped);
+                        compile_command.command = command;
+                    }
+                }
+                None => debug!("无法拆分命令, 保留原样: {:?}", compile_command.command),
+            }
+        }
+        if !self.prepend_flags.is_empty() || !self.append_flags.is_empty() {
+            match compile_command::add_flags(
+                &compile_command.command,
+                &self.prepend_flags,
+                &self.append_flags,
+            ) {
+                Some(command) => compile_command.command = command,
+                None => debug!("无法拆分命令, 保留原样: {:?}", compile_command.command),
+            }
+        }
+        if !self.env.is_empty() {
+            compile_command.command = format!("{} {}", self.env.join(" "), compile_command.command);
+        }
+        ctx.prefix_map.apply(
+            &mut compile_command.directory,
+            &mut compile_command.file,
+            &mut compile_command.command,
+        );
+        if let Some(relative_to) = &ctx.relative_to {
+            compile_command.file =
+                relative_to.apply(&compile_command.directory, compile_command.file);
+            compile_command.directory = relative_to.apply_directory(compile_command.directory);
+        }
+        compile_command.directory = ctx.path_style.apply(compile_command.directory);
+        compile_command.file = ctx.path_style.apply(compile_command.file);
+
+        // 打印符合条件的编译命令
+        debug!("{}", command);
+
+        if self.skip_entries > 0 {
+            debug!("按 --skip-entries 丢弃 {:?}", compile_command.file);
+            self.skip_entries -= 1;
+            return Ok(());
+        }
+        if self.is_full() {
+            if self.on_limit == OnLimit::Error {
+                self.limit_exceeded = true;
+            }
+            return Ok(());
+        }
+        match (&mut self.dep_files, &captured) {
+            (Some(pending), Some((directory, _))) => pending.push(PendingEntry {
+                directory: directory.clone(),
+                command: command.to_string(),
+                compile_command,
+            }),
+            _ => self.output.write(compile_command).await?,
+        }
+        self.progress.record();
+        self.stats.entries += 1;
+        if let (Some(headers), Some((directory, source))) = (&mut self.headers, captured) {
+            headers.push(&directory, command, &source);
+        }
+        Ok(())
+    }
+
+    // 是否应该因为 --max-entries 停止: truncate 时写满即停, error 时出现超出的条目才停
+    pub fn limit_reached(&self) -> bool {
+        match self.on_limit {
+            OnLimit::Truncate => self.is_full(),
+            OnLimit::Error => self.limit_exceeded,
+        }
+    }
+
+    // 是否已经写满 --max-entries 条
+    fn is_full(&self) -> bool {
+        self.max_entries
+            .is_some_and(|max_entries| self.stats.entries >= max_entries)
+    }
+
+    // 删除还没写完的输出, 用于构建命令无法启动或 --strict 遇到无效条目的情况
+    pub async fn discard(mut self) {
+        self.progress.finish();
+        self.output.discard().await;
+        if let Some(links) = self.links {
+            links.discard().await;
+        }
+    }
+
+    // 补录从依赖文件中找到的头文件, 并写出等待读取依赖文件的条目
+    async fn record_pending(&mut self) -> io::Result<()> {
+        if let Some(headers) = self.headers.take() {
+            let entries = headers.entries(&self.ctx).await;
+            info!("从依赖文件中找到 {} 个头文件", entries.len());
+            for entry in entries {
+                self.record(entry.directory, &entry.command, entry.file)
+                    .await?;
+            }
+        }
+        if let Some(pending) = self.dep_files.take() {
+            let mut found = 0;
+            for entry in pending {
+                let mut compile_command = entry.compile_command;
+                compile_command.headers =
+                    headers::read_dep_headers(&entry.directory, &entry.command, &self.ctx).await;
+                found += usize::from(compile_command.headers.is_some());
+                self.output.write(compile_command).await?;
+            }
+            info!("读取了 {} 个条目的依赖文件", found);
+        }
+        Ok(())
+    }
+
+    // 写完输出并结束进度显示, 返回统计
+    pub async fn finish(mut self) -> io::Result<Stats> {
+        self.progress.finish();
+        // 补录的条目同样可能在 --strict 下无效, 这时不留下写了一半的输出
+        if let Err(err) = self.record_pending().await {
+            self.discard().await;
+            return Err(err);
+        }
+        self.output.finish().await?;
+        if let Some(non_matching) = self.non_matching {
+            non_matching.finish().await?;
+        }
+        if let Some(links) = self.links {
+            links.finish().await?;
+        }
+        self.stats.unmapped_prefixes = self.ctx.prefix_map.unmapped();
+        for (pattern, excluded) in self.exclude.counts() {
+            if excluded == 0 {
+                warn!("--exclude {:?} 没有匹配任何条目", pattern);
+            } else {
+                info!("--exclude {:?} 排除了 {} 个条目", pattern, excluded);
+            }
+        }
+        for (pattern, excluded) in self.exclude_dirs.counts() {
+            if excluded == 0 {
+                warn!("--exclude-dir-pattern {:?} 没有匹配任何条目", pattern);
+            } else {
+                info!("--exclude-dir-pattern {:?} 排除了 {} 个条目", pattern, excluded);
+            }
+        }
+        if self.only_under.outside() > 0 {
+            info!(
+                "{} 个条目的源文件不在 --only-under 目录之下, 已跳过",
+                self.only_under.outside()
+            );
+        }
+        if let Some(relative_to) = &self.ctx.relative_to {
+            if relative_to.failed() > 0 {
+                warn!(
+                    "{} 个路径无法改写为相对路径, 保留为绝对路径",
+                    relative_to.failed()
+                );
+            }
+        }
+        if self.system.dropped() > 0 {
+            info!(
+                "{} 个条目的源文件位于系统目录下, 已跳过",
+                self.system.dropped()
+            );
+        }
+        if let Some(resolver) = &self.compiler_resolver {
+            if resolver.unresolved() > 0 {
+                warn!(
+                    "{} 个条目的编译器无法解析为绝对路径, 保持原样",
+                    resolver.unresolved()
+                );
+            }
+        }
+        Ok(self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::PathStyle;
+    use crate::testing::{recorder, TempDir, VecWriter};
+
+    fn context(directory: &str) -> ParseContext {
+        ParseContext::new(&[], &[], &[], directory.to_string(), PathStyle::Native)
+    }
+
+    // 逐行交给 recorder, 返回写出的条目和统计
+    async fn record_lines(
+        mut recorder: Recorder,
+        lines: &[&str],
+        writer: &VecWriter,
+    ) -> (Vec<CompileCommand>, Stats) {
+        for line in lines {
+            recorder.process_line(line).await.unwrap();
+        }
+        let stats = recorder.finish().await.unwrap();
+        (writer.entries(), stats)
+    }
+
+    const MIXED_LOG: &[&str] = &[
+        "gcc -c a.c -o a.o",
+        "clang -c b.c -o b.o",
+        "/usr/bin/clang++ -c c.cpp -o c.o",
+        "g++ -c d.cpp -o d.o",
+    ];
+
+    #[tokio::test]
+    async fn only_compiler_drops_other_compilers() {
+        let writer = VecWriter::default();
+        let mut recorder = recorder(context("/src"), &writer);
+        recorder.only_compilers = vec!["clang".to_string()];
+        let (entries, stats) = record_lines(recorder, MIXED_LOG, &writer).await;
+        let files: Vec<&str> = entries.iter().map(|entry| entry.file.as_str()).collect();
+        assert_eq!(files, ["b.c"]);
+        assert_eq!(stats.excluded, 3);
+    }
+
+    #[tokio::test]
+    async fn only_compiler_matches_the_name_without_path() {
+        let writer = VecWriter::default();
+        let mut recorder = recorder(context("/src"), &writer);
+        recorder.only_compilers = vec!["clang".to_string(), "clang++".to_string()];
+        let (entries, _) = record_lines(recorder, MIXED_LOG, &writer).await;
+        let files: Vec<&str> = entries.iter().map(|entry| entry.file.as_str()).collect();
+        assert_eq!(files, ["b.c", "c.cpp"]);
+    }
+
+    #[tokio::test]
+    async fn hipcc_entry_is_recorded_like_other_compilers() {
+        let writer = VecWriter::default();
+        let line = "hipcc -c -O2 --amdgpu-target=gfx906 kernel.hip -o kernel.o";
+        let (entries, _) = record_lines(recorder(context("/src"), &writer), &[line], &writer).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].directory, "/src");
+        assert_eq!(entries[0].command, line);
+        assert_eq!(entries[0].file, "kernel.hip");
+    }
+
+    #[tokio::test]
+    async fn link_commands_go_to_their_own_database() {
+        let dir = TempDir::new();
+        let link_db = dir.path().join("link_commands.json");
+        let writer = VecWriter::default();
+        let mut recorder = recorder(context("/src"), &writer);
+        recorder.links = Some(LinkRecorder::create(&link_db, false).await.unwrap());
+        let lines = [
+            "gcc -c a.c -o a.o",
+            "gcc -c b.c -o b.o",
+            "gcc a.o b.o -o app -lm",
+            "ar rcs libab.a a.o b.o",
+            "/usr/bin/ld -o app2 a.o",
+        ];
+        let (entries, _) = record_lines(recorder, &lines, &writer).await;
+
+        let files: Vec<&str> = entries.iter().map(|entry| entry.file.as_str()).collect();
+        assert_eq!(files, ["a.c", "b.c"]);
+        let links: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&link_db).unwrap()).unwrap();
+        let outputs: Vec<&str> = links
+            .iter()
+            .map(|link| link["output"].as_str().unwrap())
+            .collect();
+        assert_eq!(outputs, ["app", "libab.a", "app2"]);
+        assert_eq!(links[0]["directory"], "/src");
+        assert_eq!(links[0]["command"], "gcc a.o b.o -o app -lm");
+    }
+
+    #[tokio::test]
+    async fn quoted_source_path_is_recorded_unquoted() {
+        let writer = VecWriter::default();
+        let line = r#"g++ -c "src/my module/foo.cpp" -o foo.o"#;
+        let (entries, _) = record_lines(recorder(context("/src"), &writer), &[line], &writer).await;
+        assert_eq!(entries[0].file, "src/my module/foo.cpp");
+        assert_eq!(entries[0].command, line);
+    }
+
+    // 生成的 100 万行日志的解析吞吐量, 用 cargo test --release -- --ignored --nocapture 运行
+    #[tokio::test]
+    #[ignore]
+    async fn throughput_on_a_million_line_log() {
+        const LINES: usize = 1_000_000;
+        let log: Vec<String> = (0..LINES)
+            .map(|i| match i % 4 {
+                0 => format!(
+                    "/usr/bin/c++ -DNDEBUG -I/src/include -O2 -std=c++17 -o obj/{}.o -c /src/{}.cpp",
+                    i, i
+                ),
+                1 => format!("[{}/{}] Building CXX object obj/{}.o", i, LINES, i),
+                2 => "make[2]: Entering directory '/src/build'".to_string(),
+                _ => format!("/src/{}.cpp:10:5: warning: unused variable 'x'", i),
+            })
+            .collect();
+
+        let writer = VecWriter::default();
+        let mut recorder = recorder(context("/src"), &writer);
+        let started = std::time::Instant::now();
+        for line in &log {
+            recorder.process_line(line).await.unwrap();
+        }
+        let stats = recorder.finish().await.unwrap();
+        let elapsed = started.elapsed();
+        eprintln!(
+            "{} 行, {} 条, 用时 {:.2?}, {:.0} 行/秒",
+            stats.lines,
+            stats.entries,
+            elapsed,
+            LINES as f64 / elapsed.as_secs_f64()
+        );
+
+        // 条目的顺序与输入一致
+        let files = writer.files();
+        assert_eq!(files.len(), LINES / 4);
+        assert!(files
+            .iter()
+            .enumerate()
+            .all(|(n, file)| *file == format!("/src/{}.cpp", n * 4)));
+    }
+
+    #[tokio::test]
+    async fn multi_arch_command_is_recorded_verbatim() {
+        let writer = VecWriter::default();
+        let line = "xcrun clang -x objective-c -arch x86_64 -arch arm64 -c /src/main.m -o main.o";
+        let (entries, _) = record_lines(recorder(context("/src"), &writer), &[line], &writer).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].command,
+            "clang -x objective-c -arch x86_64 -arch arm64 -c /src/main.m -o main.o"
+        );
+        assert_eq!(entries[0].file, "/src/main.m");
+    }
+
+    #[tokio::test]
+    async fn windows_paths_follow_the_path_style() {
+        let line = r"C:\LLVM\bin\clang.exe -c C:\src\a.c -o a.obj";
+        for (style, directory, file) in [
+            (PathStyle::Unix, "C:/build", "C:/src/a.c"),
+            (PathStyle::Native, r"C:\build", r"C:\src\a.c"),
+        ] {
+            let writer = VecWriter::default();
+            let mut ctx = context(r"C:\build");
+            ctx.path_style = style;
+            let (entries, _) = record_lines(recorder(ctx, &writer), &[line], &writer).await;
+            assert_eq!(entries.len(), 1, "{:?}", style);
+            assert_eq!(entries[0].directory, directory);
+            assert_eq!(entries[0].file, file);
+            assert_eq!(entries[0].command, line);
+        }
+    }
+
+    #[tokio::test]
+    async fn unmapped_prefixes_are_counted_in_stats() {
+        let writer = VecWriter::default();
+        let mut ctx = context("/workspace/build");
+        ctx.prefix_map =
+            paths::PrefixMap::new(vec![("/workspace".to_string(), "/home/me/src".to_string())]);
+        let lines = [
+            "gcc -I/workspace/inc -c /workspace/a.c -o a.o",
+            // 相对路径跟随 directory
+            "gcc -c b.c -o b.o",
+            "gcc -c /opt/vendor/c.c -o c.o",
+        ];
+        let (entries, stats) = record_lines(recorder(ctx, &writer), &lines, &writer).await;
+        assert_eq!(entries[0].directory, "/home/me/src/build");
+        assert_eq!(entries[0].file, "/home/me/src/a.c");
+        assert_eq!(
+            entries[0].command,
+            "gcc -I/home/me/src/inc -c /home/me/src/a.c -o a.o"
+        );
+        assert_eq!(entries[1].file, "b.c");
+        assert_eq!(entries[2].file, "/opt/vendor/c.c");
+        assert_eq!(stats.unmapped_prefixes, 1);
+    }
+
+    #[tokio::test]
+    async fn shell_script_commands_follow_cd() {
+        let writer = VecWriter::default();
+        let lines = [
+            "sh -c 'cd sub && gcc -c a.c -o a.o'",
+            "/bin/sh -c 'gcc -DHAVE_CONFIG_H -c b.c -o b.o'",
+            "sh -c 'cd lib; cd ../gen && gcc -c c.c -o c.o'",
+        ];
+        let (entries, _) = record_lines(recorder(context("/src"), &writer), &lines, &writer).await;
+        let recorded: Vec<(&str, &str, &str)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.directory.as_str(),
+                    entry.file.as_str(),
+                    entry.command.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                ("/src/sub", "a.c", "gcc -c a.c -o a.o"),
+                ("/src", "b.c", "gcc -DHAVE_CONFIG_H -c b.c -o b.o"),
+                ("/src/gen", "c.c", "gcc -c c.c -o c.o"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn libtool_compile_lines_are_recorded() {
+        let writer = VecWriter::default();
+        let lines = [
+            "/bin/sh ../libtool --tag=CC --mode=compile gcc -DHAVE_CONFIG_H -I. -c foo.c -o foo.lo",
+            "libtool: compile:  gcc -DHAVE_CONFIG_H -I. -c foo.c  -fPIC -DPIC -o .libs/foo.o",
+        ];
+        let (entries, _) = record_lines(recorder(context("/src"), &writer), &lines, &writer).await;
+        let commands: Vec<&str> = entries.iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(
+            commands,
+            [
+                "gcc -DHAVE_CONFIG_H -I. -c foo.c -o foo.lo",
+                "gcc -DHAVE_CONFIG_H -I. -c foo.c  -fPIC -DPIC -o .libs/foo.o",
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.file == "foo.c"));
+    }
+}
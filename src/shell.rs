@@ -0,0 +1,258 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    // 引号没有闭合
+    UnterminatedQuote(char),
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::UnterminatedQuote(quote) => write!(f, "unterminated {} quote", quote),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+// 按 POSIX shell 的规则把命令行拆分为参数, 支持单引号、双引号和反斜杠转义
+pub fn split(input: &str) -> Result<Vec<String>, SplitError> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    // 区分 "没有参数" 和 "空字符串参数" (例如 "")
+    let mut in_word = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    // 反斜杠换行是续行, 不产生任何字符
+                    Some('\n') => {}
+                    Some(next) => current.push(next),
+                    None => current.push('\\'),
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(next) => current.push(next),
+                        None => return Err(SplitError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // 双引号内反斜杠只转义这几个字符
+                        Some('\\') => match chars.next() {
+                            Some(next @ ('"' | '\\' | '$' | '`')) => current.push(next),
+                            Some('\n') => {}
+                            Some(next) => {
+                                current.push('\\');
+                                current.push(next);
+                            }
+                            None => return Err(SplitError::UnterminatedQuote('"')),
+                        },
+                        Some(next) => current.push(next),
+                        None => return Err(SplitError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+// 按 Windows 命令行的规则拆分参数: 反斜杠是路径分隔符, 只在双引号前才是转义
+// 单引号没有特殊含义, 如 C:\tools\cl.exe "C:\Program Files\a.c"
+pub fn split_windows(input: &str) -> Result<Vec<String>, SplitError> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut in_quotes = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() && !in_quotes => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            '\\' => {
+                in_word = true;
+                // 连续的反斜杠后面跟着双引号时, 每两个反斜杠代表一个, 剩下的一个转义双引号
+                let mut count = 1;
+                while chars.peek() == Some(&'\\') {
+                    chars.next();
+                    count += 1;
+                }
+                if chars.peek() == Some(&'"') {
+                    current.extend(std::iter::repeat_n('\\', count / 2));
+                    if count % 2 == 1 {
+                        chars.next();
+                        current.push('"');
+                    }
+                } else {
+                    current.extend(std::iter::repeat_n('\\', count));
+                }
+            }
+            '"' => {
+                in_word = true;
+                in_quotes = !in_quotes;
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(SplitError::UnterminatedQuote('"'));
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+// 命令是否使用 Windows 风格的路径: 有盘符开头的路径, 或者反斜杠后面是路径中的字符
+// POSIX 的命令里反斜杠通常只用来转义空格和引号, 如 My\ Project、-DNAME=\"x\"
+pub fn is_windows_command(input: &str) -> bool {
+    let bytes = input.as_bytes();
+    bytes.windows(3).enumerate().any(|(index, window)| {
+        let starts_word = index == 0 || matches!(bytes[index - 1], b' ' | b'\t' | b'"');
+        starts_word
+            && window[0].is_ascii_alphabetic()
+            && window[1] == b':'
+            && window[2] == b'\\'
+    }) || bytes
+        .windows(2)
+        .any(|pair| pair[0] == b'\\' && (pair[1].is_ascii_alphanumeric() || pair[1] == b'_'))
+}
+
+// 拆分构建输出中的一条命令, 使用 Windows 路径的命令按 Windows 的规则拆分
+pub fn split_command(input: &str) -> Result<Vec<String>, SplitError> {
+    if is_windows_command(input) {
+        split_windows(input)
+    } else {
+        split(input)
+    }
+}
+
+// 按 POSIX shell 的规则拼接参数, 是 split 的逆操作
+pub fn join<S: AsRef<str>>(words: &[S]) -> String {
+    words
+        .iter()
+        .map(|word| quote(word.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// 是否为命令前的环境变量赋值, 如 CPATH=/opt/include
+pub fn is_env_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// 检查命令行给出的环境变量名
+pub fn parse_env_name(name: &str) -> Result<String, String> {
+    if is_env_assignment(&format!("{}=", name)) {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid environment variable name {:?}", name))
+    }
+}
+
+// 需要时给参数加上单引号, 单引号本身写成 '\''
+pub fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=+,:@%^".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(input: &str) -> Vec<String> {
+        split(input).unwrap()
+    }
+
+    #[test]
+    fn defines_with_spaces_stay_one_argument() {
+        assert_eq!(words(r#"gcc -DFOO="a b" -c a.c"#), ["gcc", "-DFOO=a b", "-c", "a.c"]);
+        assert_eq!(words("gcc '-DFOO=a b' -c a.c"), ["gcc", "-DFOO=a b", "-c", "a.c"]);
+    }
+
+    #[test]
+    fn defines_with_equals() {
+        assert_eq!(words("cc -DX=1 -DY= -DZ"), ["cc", "-DX=1", "-DY=", "-DZ"]);
+        assert_eq!(words("cc -DX='1 2'"), ["cc", "-DX=1 2"]);
+        assert_eq!(words("cc -DX=a=b"), ["cc", "-DX=a=b"]);
+    }
+
+    #[test]
+    fn escaped_quotes_keep_the_quote_characters() {
+        // 宏的值本身是字符串字面量
+        assert_eq!(words(r#"cc -DVERSION=\"1.2\""#), ["cc", r#"-DVERSION="1.2""#]);
+        assert_eq!(words(r#"cc "-DVERSION=\"1.2\"""#), ["cc", r#"-DVERSION="1.2""#]);
+        assert_eq!(words(r#"cc '-DMSG="a b"'"#), ["cc", r#"-DMSG="a b""#]);
+        assert_eq!(words(r"cc -DQ=\'x\'"), ["cc", "-DQ='x'"]);
+    }
+
+    #[test]
+    fn double_quotes_only_escape_special_characters() {
+        assert_eq!(words(r#"cc "-DP=a\b" "-DD=\$x""#), ["cc", r"-DP=a\b", "-DD=$x"]);
+    }
+
+    #[test]
+    fn empty_and_unterminated_quotes() {
+        assert_eq!(words(r#"cc "" -c"#), ["cc", "", "-c"]);
+        assert_eq!(split("cc -DX='1"), Err(SplitError::UnterminatedQuote('\'')));
+        assert_eq!(split(r#"cc "-DX=1"#), Err(SplitError::UnterminatedQuote('"')));
+    }
+
+    #[test]
+    fn join_round_trips_through_split() {
+        let args = ["cc", "-DFOO=a b", r#"-DVERSION="1.2""#, "-DQ='x'", "", "a.c"];
+        assert_eq!(words(&join(&args)), args);
+    }
+
+    #[test]
+    fn windows_commands_keep_backslashes() {
+        assert_eq!(
+            split_command(r#"C:\llvm\bin\clang.exe -DX=\"1\" "C:\My Files\a.c""#).unwrap(),
+            [r"C:\llvm\bin\clang.exe", r#"-DX="1""#, r"C:\My Files\a.c"]
+        );
+    }
+}
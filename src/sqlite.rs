@@ -0,0 +1,188 @@
+use std::io;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::compile_command::CompileCommand;
+use crate::output::{CompileCommandWriter, WriteFuture};
+
+// 与 compile_commands.json 的条目一一对应, 同一个源文件只保留最后一条
+const SCHEMA: &str = "CREATE TABLE compile_commands (
+    file TEXT PRIMARY KEY,
+    directory TEXT NOT NULL,
+    command TEXT NOT NULL
+)";
+
+// 把条目写入 SQLite 数据库, 所有条目在同一个事务中, 结束时提交
+pub struct SqliteWriter {
+    // 提交后关闭并取走
+    connection: Option<Connection>,
+}
+
+impl SqliteWriter {
+    // 创建新的数据库, 已存在的文件会被覆盖
+    pub fn create(path: &Path) -> io::Result<SqliteWriter> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute_batch(SCHEMA)
+            .and_then(|_| connection.execute_batch("BEGIN"))
+            .map_err(io::Error::other)?;
+        Ok(SqliteWriter {
+            connection: Some(connection),
+        })
+    }
+
+    pub fn write(&mut self, compile_command: &CompileCommand) -> io::Result<()> {
+        let connection = self.connection.as_ref().ok_or_else(closed)?;
+        connection
+            .prepare_cached(
+                "INSERT OR REPLACE INTO compile_commands (file, directory, command) VALUES (?1, ?2, ?3)",
+            )
+            .and_then(|mut statement| {
+                statement.execute(params![
+                    compile_command.file,
+                    compile_command.directory,
+                    compile_command.command
+                ])
+            })
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    // 提交事务并关闭数据库, 之后才能改名
+    pub fn finish(&mut self) -> io::Result<()> {
+        let connection = self.connection.take().ok_or_else(closed)?;
+        connection
+            .execute_batch("COMMIT")
+            .map_err(io::Error::other)?;
+        connection
+            .close()
+            .map_err(|(_, err)| io::Error::other(err))
+    }
+}
+
+// 写入是同步的, 每条都很快, 不单独放到阻塞线程
+impl CompileCommandWriter for SqliteWriter {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        let result = self.write(compile_command);
+        Box::pin(async { result })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        let result = SqliteWriter::finish(self);
+        Box::pin(async { result })
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::other("数据库已经关闭")
+}
+
+// 在数据库中查找一个源文件的条目; 相对路径还会按当前目录解析后再找一次
+pub fn query(database: &Path, file: &str) -> rusqlite::Result<Option<CompileCommand>> {
+    let connection =
+        Connection::open_with_flags(database, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut candidates = vec![file.to_string()];
+    if let Ok(resolved) = Path::new(file).canonicalize() {
+        candidates.push(resolved.to_string_lossy().to_string());
+    }
+
+    let mut statement = connection
+        .prepare("SELECT directory, command, file FROM compile_commands WHERE file = ?1")?;
+    for candidate in candidates {
+        let entry = statement
+            .query_row(params![candidate], |row| {
+                Ok(CompileCommand {
+                    directory: row.get(0)?,
+                    command: row.get(1)?,
+                    file: row.get(2)?,
+                    compiler: None,
+                    headers: None,
+                })
+            })
+            .optional()?;
+        if entry.is_some() {
+            return Ok(entry);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Output;
+    use crate::testing::TempDir;
+
+    fn entry(directory: &str, file: &str, command: &str) -> CompileCommand {
+        CompileCommand {
+            directory: directory.to_string(),
+            command: command.to_string(),
+            file: file.to_string(),
+            compiler: None,
+            headers: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_round_trip_through_the_database() {
+        let dir = TempDir::new();
+        let path = dir.write("compile_commands.sqlite", "not a database");
+        let mut output = Output::sqlite(&path).unwrap();
+        for compile_command in [
+            entry("/src", "/src/a.c", "cc -O0 -c a.c"),
+            entry("/src/lib", "/src/lib/b.c", "cc -c 'b.c'"),
+            entry("/src", "/src/a.c", "cc -O2 -c a.c"),
+        ] {
+            output.write(compile_command).await.unwrap();
+        }
+        output.finish().await.unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let columns: Vec<String> = connection
+            .prepare("SELECT name FROM pragma_table_info('compile_commands') ORDER BY cid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(columns, ["file", "directory", "command"]);
+        let rows: Vec<(String, String, String)> = connection
+            .prepare("SELECT file, directory, command FROM compile_commands ORDER BY file")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let rows: Vec<(&str, &str, &str)> = rows
+            .iter()
+            .map(|(file, directory, command)| (file.as_str(), directory.as_str(), command.as_str()))
+            .collect();
+        // 同一个源文件只保留最后一条
+        assert_eq!(
+            rows,
+            [
+                ("/src/a.c", "/src", "cc -O2 -c a.c"),
+                ("/src/lib/b.c", "/src/lib", "cc -c 'b.c'"),
+            ]
+        );
+
+        let found = query(&path, "/src/lib/b.c").unwrap().unwrap();
+        assert_eq!(found.directory, "/src/lib");
+        assert_eq!(found.command, "cc -c 'b.c'");
+        assert!(query(&path, "/src/c.c").unwrap().is_none());
+    }
+
+    #[test]
+    fn writer_is_closed_after_finish() {
+        let dir = TempDir::new();
+        let mut writer = SqliteWriter::create(&dir.path().join("db.sqlite")).unwrap();
+        writer.write(&entry("/src", "a.c", "cc -c a.c")).unwrap();
+        SqliteWriter::finish(&mut writer).unwrap();
+        assert!(writer.write(&entry("/src", "b.c", "cc -c b.c")).is_err());
+        assert!(SqliteWriter::finish(&mut writer).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// 测试共用的工具
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::compile_command::CompileCommand;
+use crate::filter::{ExcludeFilter, RootFilter, SystemFilter};
+use crate::output::{CompileCommandWriter, Output, OutputTask, WriteFuture};
+use crate::parse::ParseContext;
+use crate::progress::{Progress, ProgressMode};
+use crate::recorder::{OnLimit, Recorder, Stats, Validation};
+use crate::xcode::XcodeParser;
+
+// 测试用的临时目录, 离开作用域时连同其中的文件一起删除
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        // 同一进程中的测试并行运行, 用计数器区分
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "bear_rs-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        // 临时目录本身可能是符号链接, 与解析后的路径比较时要用真实路径
+        TempDir {
+            path: path.canonicalize().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 在目录下写一个文件 (需要时创建上级目录), 返回它的路径
+    pub fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// 只在内存中记录收到的条目, 克隆出的 writer 共享同一份记录,
+// 交给 Output 之后仍然可以从测试中查看
+#[derive(Clone, Default)]
+pub struct VecWriter {
+    entries: Arc<Mutex<Vec<CompileCommand>>>,
+    finished: Arc<AtomicBool>,
+}
+
+impl VecWriter {
+    pub fn entries(&self) -> Vec<CompileCommand> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn files(&self) -> Vec<String> {
+        self.entries().into_iter().map(|entry| entry.file).collect()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+impl CompileCommandWriter for VecWriter {
+    fn write_entry<'a>(&'a mut self, compile_command: &'a CompileCommand) -> WriteFuture<'a> {
+        self.entries.lock().unwrap().push(compile_command.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish(&mut self) -> WriteFuture<'_> {
+        self.finished.store(true, Ordering::Relaxed);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// 除了 ctx 之外都是默认设置的 recorder, 条目写入 writer
+pub fn recorder(ctx: ParseContext, writer: &VecWriter) -> Recorder {
+    Recorder {
+        ctx,
+        validation: Validation::Off,
+        progress: Progress::new(ProgressMode::Off),
+        output: OutputTask::spawn(Output::from_writer(writer.clone())),
+        compiler_resolver: None,
+        exclude: ExcludeFilter::default(),
+        exclude_dirs: ExcludeFilter::default(),
+        only_under: RootFilter::default(),
+        system: SystemFilter::default(),
+        env: Vec::new(),
+        emit_compiler: false,
+        only_compilers: Vec::new(),
+        language: None,
+        keep_probes: false,
+        canonicalize_includes: false,
+        strip_flags: Vec::new(),
+        prepend_flags: Vec::new(),
+        append_flags: Vec::new(),
+        working_directory: None,
+        links: None,
+        headers: None,
+        dep_files: None,
+        skip_entries: 0,
+        max_entries: None,
+        on_limit: OnLimit::Truncate,
+        limit_exceeded: false,
+        xcode: XcodeParser::new(false),
+        non_matching: None,
+        stats: Stats::default(),
+    }
+}
@@ -0,0 +1,119 @@
+use tracing::{debug, info};
+
+use crate::shell;
+
+// xcodebuild 把每个编译步骤输出为一个块:
+//
+// CompileC <目标文件> <源文件> normal arm64 c++ com.apple.compilers.llvm.clang.1_0.compiler
+//     cd <目录>
+//     export LANG\=en_US.US-ASCII
+//     /Applications/Xcode.app/.../clang -x c++ ... -c <源文件> -o <目标文件>
+//
+// 块头给出源文件, cd 行给出目录, 之后的第一行是完整的编译命令
+pub struct XcodeParser {
+    // --xcodebuild 或者已经见过 CompileC 块
+    detected: bool,
+    block: Option<Block>,
+}
+
+struct Block {
+    // SwiftCompile 等不产生条目的块为 None, 整个块被跳过
+    source: Option<String>,
+    directory: Option<String>,
+}
+
+// 块头的第一个单词; 只有 CompileC 产生条目, Swift 的块整个跳过
+const BLOCK_KINDS: &[&str] = &[
+    "CompileC",
+    "SwiftCompile",
+    "CompileSwift",
+    "CompileSwiftSources",
+];
+
+// 块中解析出的编译命令
+pub struct XcodeCommand {
+    pub directory: Option<String>,
+    pub command: String,
+    pub source: String,
+}
+
+// 一行输出的处理结果
+pub enum XcodeLine {
+    // 不属于任何块, 按普通的构建输出处理
+    Other,
+    // 块中的一行, 不需要再处理
+    Consumed,
+    // 块中的编译命令
+    Command(XcodeCommand),
+}
+
+impl XcodeParser {
+    pub fn new(detected: bool) -> XcodeParser {
+        XcodeParser {
+            detected,
+            block: None,
+        }
+    }
+
+    pub fn push(&mut self, line: &str) -> XcodeLine {
+        let indented = line.starts_with([' ', '\t']);
+        if indented {
+            if let Some(block) = &mut self.block {
+                return block.push(line.trim());
+            }
+        } else if self
+            .block
+            .take()
+            .is_some_and(|block| block.source.is_some())
+        {
+            debug!("xcodebuild 块中没有找到编译命令");
+        }
+
+        let Some(kind) = BLOCK_KINDS.iter().find(|kind| {
+            line.strip_prefix(**kind)
+                .is_some_and(|rest| rest.starts_with(' '))
+        }) else {
+            return XcodeLine::Other;
+        };
+        let source = if *kind == "CompileC" {
+            // CompileC <目标文件> <源文件> ..., 路径中的空格用反斜杠转义
+            match shell::split(line) {
+                Ok(words) if words.len() > 2 => Some(words[2].clone()),
+                _ => return XcodeLine::Other,
+            }
+        } else {
+            None
+        };
+        if !self.detected {
+            info!("检测到 xcodebuild 的输出, 按 CompileC 块解析编译命令");
+            self.detected = true;
+        }
+        self.block = Some(Block {
+            source,
+            directory: None,
+        });
+        XcodeLine::Consumed
+    }
+}
+
+impl Block {
+    fn push(&mut self, line: &str) -> XcodeLine {
+        if let Some(directory) = line.strip_prefix("cd ") {
+            self.directory = shell::split(directory)
+                .ok()
+                .and_then(|words| words.into_iter().next());
+            return XcodeLine::Consumed;
+        }
+        if line.starts_with("export ") || line.starts_with("builtin-") {
+            return XcodeLine::Consumed;
+        }
+        match self.source.take() {
+            Some(source) => XcodeLine::Command(XcodeCommand {
+                directory: self.directory.take(),
+                command: line.to_string(),
+                source,
+            }),
+            None => XcodeLine::Consumed,
+        }
+    }
+}